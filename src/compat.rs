@@ -0,0 +1,249 @@
+use std::fmt::Display;
+
+use crate::json_schema::integer_value;
+use crate::report::escape;
+use crate::schema::{Schema, TypeMask};
+
+/// Which direction of compatibility is required of a new schema.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompatMode {
+    /// Consumers of the baseline can read data of the new schema.
+    #[default]
+    Backward,
+    /// Consumers of the new schema can read data of the baseline.
+    Forward,
+    /// Both.
+    Full,
+}
+
+/// Something a consumer of one schema could not read in data of another.
+#[derive(Debug)]
+pub struct Incompatibility {
+    /// Where in the data, as a JSON pointer with array items and map values as `*`.
+    pub pointer: String,
+    pub message: String,
+}
+
+impl Display for Incompatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "(root)"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+/// Everything a consumer of `reader` could not read in data described by `writer`,
+/// following Avro and Protobuf style rules:
+///
+/// - Fields the reader does not know about are ignored, so adding fields is fine.
+/// - Fields the reader requires must always be present.
+/// - Every type the writer produces must be one the reader accepts. Integers may be
+///   promoted to floating point numbers, but not the other way around.
+/// - Enum values must all be known to the reader.
+pub fn check(reader: &Schema, writer: &Schema) -> Vec<Incompatibility> {
+    let mut incompatibilities = Vec::new();
+    check_node(reader, writer, String::new(), &mut incompatibilities);
+    incompatibilities
+}
+
+fn check_node(
+    reader: &Schema,
+    writer: &Schema,
+    pointer: String,
+    incompatibilities: &mut Vec<Incompatibility>,
+) {
+    let mut report = |message: String| {
+        incompatibilities.push(Incompatibility {
+            pointer: pointer.clone(),
+            message,
+        })
+    };
+
+    // Maps are objects too, integers are integers whatever their sign,
+    // and integers can always be read as numbers
+    let mut accepted = reader.type_mask;
+    if accepted.intersects(TypeMask::OBJECT | TypeMask::LARGE_OBJ) {
+        accepted |= TypeMask::OBJECT | TypeMask::LARGE_OBJ;
+    }
+    if accepted.intersects(TypeMask::STRING | TypeMask::STRING_SET) {
+        accepted |= TypeMask::STRING | TypeMask::STRING_SET;
+    }
+    if accepted.intersects(TypeMask::I64 | TypeMask::U64) {
+        accepted |= TypeMask::I64 | TypeMask::U64;
+    }
+    if accepted.contains(TypeMask::F64) {
+        accepted |= TypeMask::I64 | TypeMask::U64;
+    }
+    let unreadable = (writer.type_mask - TypeMask::ABSENT) - accepted;
+    if !unreadable.is_empty() {
+        let expected = (reader.type_mask - TypeMask::ABSENT).names().join(", ");
+        report(format!(
+            "{} where {} is expected",
+            unreadable.names().join(", "),
+            expected
+        ));
+    }
+
+    // An enum only accepts the values it knows about
+    let strings = TypeMask::STRING | TypeMask::STRING_SET;
+    if let Some(reader_values) = &reader.string_values {
+        match &writer.string_values {
+            Some(writer_values) => {
+                let unknown: Vec<&String> = writer_values.difference(reader_values).collect();
+                if !unknown.is_empty() {
+                    let unknown = serde_json::to_string(&unknown).unwrap_or_default();
+                    report(format!("enum values {} are not known", unknown));
+                }
+            }
+            None if writer.type_mask.intersects(strings) => {
+                report("any string where an enum is expected".to_string())
+            }
+            None => {}
+        }
+    }
+
+    let integers = TypeMask::I64 | TypeMask::U64;
+    if let Some(reader_values) = &reader.integer_values {
+        match &writer.integer_values {
+            Some(writer_values) => {
+                let unknown: Vec<_> = writer_values
+                    .difference(reader_values)
+                    .map(|&value| integer_value(value))
+                    .collect();
+                if !unknown.is_empty() {
+                    let unknown = serde_json::Value::from(unknown);
+                    report(format!("enum values {} are not known", unknown));
+                }
+            }
+            None if writer.type_mask.intersects(integers) => {
+                report("any integer where an enum is expected".to_string())
+            }
+            None => {}
+        }
+    }
+
+    // The format the reader relies on must hold for the writer's strings too
+    let reader_format = reader.string_format.and_then(|format| format.name());
+    if let (Some(name), true) = (reader_format, writer.type_mask.intersects(strings)) {
+        let shared = match (reader.string_format, writer.string_format) {
+            (Some(reader_format), Some(writer_format)) => (reader_format & writer_format).name(),
+            _ => None,
+        };
+        if shared != Some(name) {
+            report(format!("strings that may not be {}", name));
+        }
+    }
+
+    if let Some(reader_items) = &reader.tuple_items {
+        let writer_len = writer.tuple_items.as_ref().map(Vec::len);
+        if writer.type_mask.contains(TypeMask::ARRAY) && writer_len != Some(reader_items.len()) {
+            report(format!(
+                "arrays that may not have exactly {} items",
+                reader_items.len()
+            ));
+        }
+    }
+
+    // Objects are compared property by property, and a map's values stand in for
+    // every property on its side
+    match (&reader.object_properties, &writer.object_properties) {
+        (Some(reader_props), Some(writer_props)) => {
+            for (key, reader_prop) in reader_props {
+                let child = format!("{}/{}", pointer, escape(key));
+                let required = reader_prop.is_required(None);
+                match writer_props.get(key) {
+                    Some(writer_prop) => {
+                        if required && !writer_prop.is_required(None) {
+                            incompatibilities.push(Incompatibility {
+                                pointer: child.clone(),
+                                message: "required field may be missing".to_string(),
+                            });
+                        }
+                        check_node(reader_prop, writer_prop, child, incompatibilities);
+                    }
+                    None if required => incompatibilities.push(Incompatibility {
+                        pointer: child,
+                        message: "required field is missing".to_string(),
+                    }),
+                    None => {}
+                }
+            }
+        }
+        (Some(reader_props), None) => {
+            if let Some(writer_values) = &writer.map_values {
+                for (key, reader_prop) in reader_props {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    if reader_prop.is_required(None) {
+                        incompatibilities.push(Incompatibility {
+                            pointer: child.clone(),
+                            message: "required field may be missing".to_string(),
+                        });
+                    }
+                    check_node(reader_prop, writer_values, child, incompatibilities);
+                }
+            }
+        }
+        (None, Some(writer_props)) => {
+            if let Some(reader_values) = &reader.map_values {
+                for (key, writer_prop) in writer_props {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    check_node(reader_values, writer_prop, child, incompatibilities);
+                }
+            }
+        }
+        (None, None) => {
+            if let (Some(reader_values), Some(writer_values)) =
+                (&reader.map_values, &writer.map_values)
+            {
+                check_node(
+                    reader_values,
+                    writer_values,
+                    format!("{}/*", pointer),
+                    incompatibilities,
+                );
+            }
+        }
+    }
+
+    // Likewise, arrays are compared position by position, and the items of an
+    // array without a fixed length stand in for every position
+    if let (Some(reader_items), Some(writer_items)) = (&reader.array_items, &writer.array_items) {
+        check_node(
+            reader_items,
+            writer_items,
+            format!("{}/*", pointer),
+            incompatibilities,
+        );
+    }
+
+    match (&reader.tuple_items, &writer.tuple_items) {
+        (Some(reader_items), Some(writer_items)) => {
+            for (index, (reader_item, writer_item)) in
+                reader_items.iter().zip(writer_items).enumerate()
+            {
+                let child = format!("{}/{}", pointer, index);
+                check_node(reader_item, writer_item, child, incompatibilities);
+            }
+        }
+        (Some(reader_items), None) => {
+            if let (None, Some(writer_items)) = (&reader.array_items, &writer.array_items) {
+                for (index, reader_item) in reader_items.iter().enumerate() {
+                    let child = format!("{}/{}", pointer, index);
+                    check_node(reader_item, writer_items, child, incompatibilities);
+                }
+            }
+        }
+        (None, Some(writer_items)) => {
+            if let (Some(reader_items), None) = (&reader.array_items, &writer.array_items) {
+                for (index, writer_item) in writer_items.iter().enumerate() {
+                    let child = format!("{}/{}", pointer, index);
+                    check_node(reader_items, writer_item, child, incompatibilities);
+                }
+            }
+        }
+        (None, None) => {}
+    }
+}
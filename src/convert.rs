@@ -0,0 +1,391 @@
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::format::StringFormat;
+use crate::report::escape;
+use crate::schema::{Schema, TypeMask};
+
+/// Every base type, for schemas that accept anything.
+const ANY: TypeMask = TypeMask::STRING
+    .union(TypeMask::BOOLEAN)
+    .union(TypeMask::NULL)
+    .union(TypeMask::ARRAY)
+    .union(TypeMask::OBJECT)
+    .union(TypeMask::I64)
+    .union(TypeMask::U64)
+    .union(TypeMask::F64);
+
+/// Convert a JSON Schema document (Draft 2020-12 or Draft-07) into a schema state,
+/// so that a hand-written contract can be merged with inferred schemas or compared to them.
+///
+/// Understands `type`, `properties`, `required`, `items`, `prefixItems`, `enum`, `const`,
+/// `format`, `additionalProperties`, `anyOf`, `oneOf` and local `$ref`s. Other keywords
+/// are ignored. A recursive `$ref` is expanded once, and only its type is kept where it recurs:
+/// its properties are unknown there, so merges take them from the other side, and
+/// `--drift`, `diff` and `compat` do not look inside.
+pub fn from_json_schema(document: &Value) -> Result<Schema> {
+    let mut converter = Converter {
+        document,
+        references: Vec::new(),
+    };
+    converter.convert(document, "")
+}
+
+struct Converter<'a> {
+    document: &'a Value,
+    /// The `$ref`s being expanded, innermost last.
+    references: Vec<&'a str>,
+}
+
+impl<'a> Converter<'a> {
+    /// Convert the subschema at `pointer` in the document, which is only used in errors.
+    fn convert(&mut self, value: &'a Value, pointer: &str) -> Result<Schema> {
+        let node = match value {
+            Value::Bool(true) => return Ok(any()),
+            Value::Bool(false) => return Ok(Schema::new(TypeMask::empty())),
+            Value::Object(node) => node,
+            _ => bail!("Expected a schema at {}", display(pointer)),
+        };
+
+        if let Some(reference) = node.get("$ref") {
+            let reference = reference
+                .as_str()
+                .with_context(|| format!("Expected a string at {}/$ref", pointer))?;
+            let target = self.resolve(reference)?;
+            // The shape of a recursive object is left unknown where it recurs,
+            // so that it is taken from data instead of being cut off
+            if self.references.contains(&reference) {
+                let mut schema = declared_type(target, reference)?;
+                schema.object_properties = None;
+                return Ok(schema);
+            }
+
+            self.references.push(reference);
+            let schema = self.convert(target, reference.trim_start_matches('#'));
+            self.references.pop();
+            return schema;
+        }
+
+        let mut schema: Option<Schema> = None;
+        for keyword in ["anyOf", "oneOf"] {
+            let Some(branches) = node.get(keyword) else {
+                continue;
+            };
+            let branches = branches
+                .as_array()
+                .with_context(|| format!("Expected an array at {}/{}", pointer, keyword))?;
+            for (index, branch) in branches.iter().enumerate() {
+                let branch = self.convert(branch, &format!("{}/{}/{}", pointer, keyword, index))?;
+                match &mut schema {
+                    Some(schema) => absorb(schema, branch),
+                    None => schema = Some(branch),
+                }
+            }
+        }
+
+        // Branches describe the types; any keywords alongside them only constrain those
+        if let Some(schema) = schema {
+            return Ok(schema);
+        }
+
+        let mut schema = match values(node) {
+            Some(values) => from_values(values),
+            None => declared_type(value, pointer)?,
+        };
+
+        if let Some(format) = node.get("format").and_then(Value::as_str) {
+            if schema.type_mask.contains(TypeMask::STRING) {
+                schema.string_format = StringFormat::from_format_name(format);
+            }
+        }
+
+        if schema.type_mask.contains(TypeMask::ARRAY) {
+            let tuple = match (node.get("prefixItems"), node.get("items")) {
+                (Some(Value::Array(items)), _) => Some(("prefixItems", items)),
+                (_, Some(Value::Array(items))) => Some(("items", items)),
+                _ => None,
+            };
+            if let Some((keyword, items)) = tuple {
+                let items: Vec<Schema> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        self.convert(item, &format!("{}/{}/{}", pointer, keyword, index))
+                    })
+                    .collect::<Result<_>>()?;
+                schema.array_lengths = Some((items.len() as u64, items.len() as u64));
+                schema.tuple_items = Some(items);
+            } else if let Some(items @ Value::Object(_)) = node.get("items") {
+                let items = self.convert(items, &format!("{}/items", pointer))?;
+                schema.array_items = Some(Box::new(items));
+            }
+        }
+
+        if schema.type_mask.contains(TypeMask::OBJECT) {
+            let required: BTreeSet<&str> = match node.get("required") {
+                Some(Value::Array(keys)) => keys.iter().filter_map(Value::as_str).collect(),
+                _ => BTreeSet::new(),
+            };
+
+            match (node.get("properties"), node.get("additionalProperties")) {
+                (Some(Value::Object(properties)), _) => {
+                    let mut converted = IndexMap::new();
+                    for (key, property) in properties {
+                        let child = format!("{}/properties/{}", pointer, escape(key));
+                        let mut property = self.convert(property, &child)?;
+                        if !required.contains(key.as_str()) {
+                            property.type_mask |= TypeMask::ABSENT;
+                        }
+                        converted.insert(key.clone(), property);
+                    }
+                    schema.object_properties = Some(converted);
+                }
+                // An object described only by its values is a map
+                (None, Some(values @ Value::Object(_))) => {
+                    let values =
+                        self.convert(values, &format!("{}/additionalProperties", pointer))?;
+                    schema.type_mask = (schema.type_mask - TypeMask::OBJECT) | TypeMask::LARGE_OBJ;
+                    schema.object_properties = None;
+                    schema.map_values = Some(Box::new(values));
+                }
+                _ => schema.object_properties = Some(IndexMap::new()),
+            }
+        }
+
+        Ok(schema)
+    }
+
+    /// The subschema a local `$ref` points to.
+    fn resolve(&self, reference: &str) -> Result<&'a Value> {
+        let Some(pointer) = reference.strip_prefix('#') else {
+            bail!("Only local references are supported, not {}", reference);
+        };
+        self.document
+            .pointer(pointer)
+            .with_context(|| format!("Cannot resolve reference {}", reference))
+    }
+}
+
+/// A schema that accepts anything, with objects of any shape.
+fn any() -> Schema {
+    Schema {
+        object_properties: Some(IndexMap::new()),
+        ..Schema::new(ANY)
+    }
+}
+
+/// The types a subschema allows, from `type` or else from the keywords it uses,
+/// without any of its children.
+fn declared_type(value: &Value, pointer: &str) -> Result<Schema> {
+    let Value::Object(node) = value else {
+        return Ok(match value {
+            Value::Bool(false) => Schema::new(TypeMask::empty()),
+            _ => any(),
+        });
+    };
+
+    let mut mask = TypeMask::empty();
+    match node.get("type") {
+        Some(Value::Array(names)) => {
+            for name in names {
+                mask |= type_mask(name, pointer)?;
+            }
+        }
+        Some(name) => mask = type_mask(name, pointer)?,
+        None if node.contains_key("properties") || node.contains_key("additionalProperties") => {
+            mask = TypeMask::OBJECT
+        }
+        None if node.contains_key("items") || node.contains_key("prefixItems") => {
+            mask = TypeMask::ARRAY
+        }
+        None => return Ok(any()),
+    }
+
+    let mut schema = Schema::new(mask);
+    if mask.contains(TypeMask::OBJECT) {
+        schema.object_properties = Some(IndexMap::new());
+    }
+    Ok(schema)
+}
+
+/// The base types of a JSON Schema `type` name.
+fn type_mask(name: &Value, pointer: &str) -> Result<TypeMask> {
+    Ok(match name.as_str() {
+        Some("string") => TypeMask::STRING,
+        Some("integer") => TypeMask::I64 | TypeMask::U64,
+        Some("number") => TypeMask::F64,
+        Some("boolean") => TypeMask::BOOLEAN,
+        Some("null") => TypeMask::NULL,
+        Some("array") => TypeMask::ARRAY,
+        Some("object") => TypeMask::OBJECT,
+        _ => bail!("Unknown type {} at {}/type", name, pointer),
+    })
+}
+
+/// The values allowed by `enum` or `const`, if either is present.
+fn values(node: &serde_json::Map<String, Value>) -> Option<&[Value]> {
+    match (node.get("enum"), node.get("const")) {
+        (Some(Value::Array(values)), _) => Some(values),
+        (_, Some(value)) => Some(std::slice::from_ref(value)),
+        _ => None,
+    }
+}
+
+/// A schema that only allows the given values.
+fn from_values(values: &[Value]) -> Schema {
+    let mut schema = Schema::new(TypeMask::empty());
+    let mut strings = BTreeSet::new();
+    let mut integers = BTreeSet::new();
+    let mut booleans = BTreeSet::new();
+    for value in values {
+        match value {
+            Value::String(value) => {
+                schema.type_mask |= TypeMask::STRING_SET;
+                strings.insert(value.clone());
+            }
+            Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+                (Some(value), _) => {
+                    schema.type_mask |= TypeMask::U64;
+                    integers.insert(value as i128);
+                }
+                (None, Some(value)) => {
+                    schema.type_mask |= TypeMask::I64;
+                    integers.insert(value as i128);
+                }
+                (None, None) => schema.type_mask |= TypeMask::F64,
+            },
+            Value::Bool(value) => {
+                schema.type_mask |= TypeMask::BOOLEAN;
+                booleans.insert(*value);
+            }
+            Value::Null => schema.type_mask |= TypeMask::NULL,
+            Value::Array(_) => schema.type_mask |= TypeMask::ARRAY,
+            Value::Object(_) => {
+                schema.type_mask |= TypeMask::OBJECT;
+                schema.object_properties = Some(IndexMap::new());
+            }
+        }
+    }
+
+    schema.string_values = (!strings.is_empty()).then_some(strings);
+    // Floating point values cannot be listed, so the integers alone do not restrict numbers
+    if !schema.type_mask.contains(TypeMask::F64) && !integers.is_empty() {
+        schema.integer_values = Some(integers);
+    }
+    if booleans.len() == 1 {
+        schema.boolean_value = booleans.pop_first();
+    }
+    schema
+}
+
+/// Fold one `anyOf` or `oneOf` branch into the schema of the branches before it.
+/// Branches usually cover different base types, so each part is taken from whichever
+/// branch has it, and merged only when both do.
+fn absorb(schema: &mut Schema, branch: Schema) {
+    let strings = TypeMask::STRING | TypeMask::STRING_SET;
+    let integers = TypeMask::I64 | TypeMask::U64;
+    let (self_mask, other_mask) = (schema.type_mask, branch.type_mask);
+    schema.type_mask |= other_mask;
+
+    if self_mask.intersects(strings) && other_mask.intersects(strings) {
+        schema.string_values = match (schema.string_values.take(), branch.string_values) {
+            (Some(mut values), Some(other_values)) => {
+                values.extend(other_values);
+                Some(values)
+            }
+            _ => {
+                // A plain string in either branch allows any string
+                schema.type_mask = (schema.type_mask - TypeMask::STRING_SET) | TypeMask::STRING;
+                None
+            }
+        };
+        schema.string_format = match (schema.string_format, branch.string_format) {
+            (Some(format), Some(other_format)) => Some(format & other_format),
+            _ => None,
+        };
+    } else if other_mask.intersects(strings) {
+        schema.string_values = branch.string_values;
+        schema.string_format = branch.string_format;
+    }
+
+    if self_mask.intersects(integers) && other_mask.intersects(integers) {
+        schema.integer_values = match (schema.integer_values.take(), branch.integer_values) {
+            (Some(mut values), Some(other_values)) => {
+                values.extend(other_values);
+                Some(values)
+            }
+            _ => None,
+        };
+    } else if other_mask.intersects(integers) {
+        schema.integer_values = branch.integer_values;
+    }
+    if schema.type_mask.contains(TypeMask::F64) {
+        schema.integer_values = None;
+    }
+
+    if self_mask.contains(TypeMask::BOOLEAN) && other_mask.contains(TypeMask::BOOLEAN) {
+        if schema.boolean_value != branch.boolean_value {
+            schema.boolean_value = None;
+        }
+    } else if other_mask.contains(TypeMask::BOOLEAN) {
+        schema.boolean_value = branch.boolean_value;
+    }
+
+    match (&mut schema.object_properties, branch.object_properties) {
+        (Some(properties), Some(mut other_properties)) => {
+            // A property only one branch has may be missing
+            for (key, property) in properties.iter_mut() {
+                match other_properties.shift_remove(key) {
+                    Some(other_property) => absorb(property, other_property),
+                    None => property.type_mask |= TypeMask::ABSENT,
+                }
+            }
+            for (key, mut property) in other_properties {
+                property.type_mask |= TypeMask::ABSENT;
+                properties.insert(key, property);
+            }
+        }
+        (None, Some(other_properties)) => schema.object_properties = Some(other_properties),
+        _ => {}
+    }
+
+    match (&mut schema.array_items, branch.array_items) {
+        (Some(items), Some(other_items)) => absorb(items, *other_items),
+        (None, Some(other_items)) => schema.array_items = Some(other_items),
+        _ => {}
+    }
+
+    match (&mut schema.tuple_items, branch.tuple_items) {
+        (Some(items), Some(other_items)) if items.len() == other_items.len() => {
+            for (item, other_item) in items.iter_mut().zip(other_items) {
+                absorb(item, other_item);
+            }
+        }
+        (Some(_), Some(_)) => {
+            schema.tuple_items = None;
+            schema.array_lengths = None;
+        }
+        (None, Some(other_items)) if !self_mask.contains(TypeMask::ARRAY) => {
+            schema.tuple_items = Some(other_items);
+            schema.array_lengths = branch.array_lengths;
+        }
+        _ => {}
+    }
+
+    match (&mut schema.map_values, branch.map_values) {
+        (Some(values), Some(other_values)) => absorb(values, *other_values),
+        (None, Some(other_values)) => schema.map_values = Some(other_values),
+        _ => {}
+    }
+}
+
+fn display(pointer: &str) -> &str {
+    if pointer.is_empty() {
+        "(root)"
+    } else {
+        pointer
+    }
+}
@@ -0,0 +1,279 @@
+use std::{collections::BTreeSet, fmt::Display, io::BufReader, path::Path};
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::convert::from_json_schema;
+use crate::report::escape;
+use crate::schema::{Schema, TypeMask};
+
+/// A schema to compare: either a state saved with `--schema`, or a JSON Schema document.
+pub enum Document {
+    State(Box<Schema>),
+    JsonSchema(Value),
+}
+
+impl Document {
+    /// Load a document, telling states apart by their `type_mask`.
+    pub fn load(path: &Path) -> Result<Document> {
+        let value: Value = serde_json::from_reader(BufReader::new(std::fs::File::open(path)?))?;
+        if value.get("type_mask").is_some() {
+            Ok(Document::State(Box::new(serde_json::from_value(value)?)))
+        } else {
+            Ok(Document::JsonSchema(value))
+        }
+    }
+
+    /// The schema state of this document, converting a JSON Schema if need be.
+    pub fn into_schema(self) -> Result<Schema> {
+        match self {
+            Document::State(schema) => Ok(*schema),
+            Document::JsonSchema(value) => from_json_schema(&value),
+        }
+    }
+}
+
+/// A structural difference between two schemas, located by JSON pointer.
+#[derive(Debug)]
+pub enum Difference {
+    Added {
+        pointer: String,
+        detail: String,
+    },
+    Removed {
+        pointer: String,
+        detail: String,
+    },
+    Changed {
+        pointer: String,
+        /// What changed, such as "type" or "enum". Empty for JSON Schema documents,
+        /// whose pointers already end in the keyword.
+        aspect: &'static str,
+        from: String,
+        to: String,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Added { pointer, detail } => write!(f, "+ {}: {}", pointer, detail),
+            Difference::Removed { pointer, detail } => write!(f, "- {}: {}", pointer, detail),
+            Difference::Changed {
+                pointer,
+                aspect: "",
+                from,
+                to,
+            } => write!(f, "~ {}: {} -> {}", pointer, from, to),
+            Difference::Changed {
+                pointer,
+                aspect,
+                from,
+                to,
+            } => write!(f, "~ {}: {} {} -> {}", pointer, aspect, from, to),
+        }
+    }
+}
+
+/// Every difference from `a` to `b`, parents before children.
+/// States are compared by meaning, JSON Schema documents keyword by keyword.
+/// A JSON Schema compared to a state is converted into a state first.
+pub fn diff(a: &Document, b: &Document) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    match (a, b) {
+        (Document::State(a), Document::State(b)) => {
+            diff_states(a, b, String::new(), &mut differences)
+        }
+        (Document::JsonSchema(a), Document::JsonSchema(b)) => {
+            diff_values(a, b, String::new(), &mut differences)
+        }
+        (Document::State(a), Document::JsonSchema(b)) => {
+            diff_states(a, &from_json_schema(b)?, String::new(), &mut differences)
+        }
+        (Document::JsonSchema(a), Document::State(b)) => {
+            diff_states(&from_json_schema(a)?, b, String::new(), &mut differences)
+        }
+    }
+    Ok(differences)
+}
+
+fn diff_states(a: &Schema, b: &Schema, pointer: String, differences: &mut Vec<Difference>) {
+    let (a_types, b_types) = (type_names(a.type_mask), type_names(b.type_mask));
+    changed("type", a_types, b_types, &pointer, differences);
+
+    changed(
+        "enum",
+        set(&a.string_values),
+        set(&b.string_values),
+        &pointer,
+        differences,
+    );
+    changed(
+        "enum",
+        set(&a.integer_values),
+        set(&b.integer_values),
+        &pointer,
+        differences,
+    );
+    let format = |schema: &Schema| {
+        let name = schema
+            .string_format
+            .as_ref()
+            .and_then(|format| format.name());
+        name.unwrap_or("none").to_string()
+    };
+    changed("format", format(a), format(b), &pointer, differences);
+
+    if let (Some(a_props), Some(b_props)) = (&a.object_properties, &b.object_properties) {
+        for (key, a_prop) in a_props {
+            let child = format!("{}/{}", pointer, escape(key));
+            match b_props.get(key) {
+                Some(b_prop) => {
+                    changed(
+                        "presence",
+                        presence(a_prop),
+                        presence(b_prop),
+                        &child,
+                        differences,
+                    );
+                    diff_states(a_prop, b_prop, child, differences);
+                }
+                None => differences.push(Difference::Removed {
+                    pointer: child,
+                    detail: describe(a_prop),
+                }),
+            }
+        }
+
+        for (key, b_prop) in b_props {
+            if !a_props.contains_key(key) {
+                differences.push(Difference::Added {
+                    pointer: format!("{}/{}", pointer, escape(key)),
+                    detail: describe(b_prop),
+                });
+            }
+        }
+    }
+
+    if let (Some(a_items), Some(b_items)) = (&a.array_items, &b.array_items) {
+        diff_states(a_items, b_items, format!("{}/*", pointer), differences);
+    }
+
+    let tuple_len = |schema: &Schema| match &schema.tuple_items {
+        Some(items) => items.len().to_string(),
+        None => "none".to_string(),
+    };
+    changed(
+        "tuple length",
+        tuple_len(a),
+        tuple_len(b),
+        &pointer,
+        differences,
+    );
+    for (index, (a_item, b_item)) in a
+        .tuple_items
+        .iter()
+        .flatten()
+        .zip(b.tuple_items.iter().flatten())
+        .enumerate()
+    {
+        diff_states(
+            a_item,
+            b_item,
+            format!("{}/{}", pointer, index),
+            differences,
+        );
+    }
+
+    if let (Some(a_values), Some(b_values)) = (&a.map_values, &b.map_values) {
+        diff_states(a_values, b_values, format!("{}/*", pointer), differences);
+    }
+}
+
+fn diff_values(a: &Value, b: &Value, pointer: String, differences: &mut Vec<Difference>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let child = format!("{}/{}", pointer, escape(key));
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff_values(a, b, child, differences),
+                    (Some(a), None) => differences.push(Difference::Removed {
+                        pointer: child,
+                        detail: a.to_string(),
+                    }),
+                    (None, Some(b)) => differences.push(Difference::Added {
+                        pointer: child,
+                        detail: b.to_string(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        // Lists of subschemas, such as `anyOf` branches, are compared position by position
+        (Value::Array(a_items), Value::Array(b_items))
+            if a_items.len() == b_items.len() && a_items.iter().any(Value::is_object) =>
+        {
+            for (index, (a, b)) in a_items.iter().zip(b_items).enumerate() {
+                diff_values(a, b, format!("{}/{}", pointer, index), differences);
+            }
+        }
+        _ if a != b => differences.push(Difference::Changed {
+            pointer,
+            aspect: "",
+            from: a.to_string(),
+            to: b.to_string(),
+        }),
+        _ => {}
+    }
+}
+
+fn changed(
+    aspect: &'static str,
+    from: String,
+    to: String,
+    pointer: &str,
+    differences: &mut Vec<Difference>,
+) {
+    if from != to {
+        differences.push(Difference::Changed {
+            pointer: pointer.to_string(),
+            aspect,
+            from,
+            to,
+        });
+    }
+}
+
+/// The base types in `mask`, telling maps apart from other objects. As in the generated
+/// JSON Schema, integers are only named separately while there are no other numbers.
+fn type_names(mask: TypeMask) -> String {
+    let names = mask.names().into_iter().filter_map(|name| match name {
+        "integer" if mask.contains(TypeMask::F64) => None,
+        "object" if !mask.contains(TypeMask::OBJECT) => Some("map"),
+        name => Some(name),
+    });
+    names.collect::<Vec<_>>().join(", ")
+}
+
+fn set<T: Serialize>(values: &Option<BTreeSet<T>>) -> String {
+    match values {
+        Some(values) => serde_json::to_string(values).unwrap_or_default(),
+        None => "none".to_string(),
+    }
+}
+
+fn presence(schema: &Schema) -> String {
+    let presence = if schema.is_required(None) {
+        "required"
+    } else {
+        "optional"
+    };
+    presence.to_string()
+}
+
+/// A property as it appears in an addition or removal.
+fn describe(schema: &Schema) -> String {
+    format!("{} ({})", type_names(schema.type_mask), presence(schema))
+}
@@ -0,0 +1,282 @@
+use std::{collections::BTreeSet, fmt::Display, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::json_schema::integer_value;
+use crate::report::escape;
+use crate::schema::{Config, Schema, TypeMask};
+
+/// A difference between a baseline schema and one inferred from newer data,
+/// located by JSON pointer as in the `--report` file.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    /// A property that the baseline never saw.
+    NewField {
+        pointer: String,
+        types: Vec<&'static str>,
+    },
+    /// A property of the baseline that was not seen in the new data.
+    RemovedField { pointer: String },
+    /// A value that can now be `null`.
+    NewlyNullable { pointer: String },
+    /// A value with types the baseline never saw, or an enum that now has too many values.
+    WidenedType {
+        pointer: String,
+        from: Vec<&'static str>,
+        to: Vec<&'static str>,
+    },
+    /// An enum with values the baseline never saw.
+    NewEnumVariants {
+        pointer: String,
+        values: Vec<serde_json::Value>,
+    },
+    /// A property that was required in the baseline, but is now sometimes missing.
+    RequiredToOptional { pointer: String },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::NewField { pointer, types } => {
+                write!(f, "+ {}: new field ({})", pointer, types.join(", "))
+            }
+            Change::RemovedField { pointer } => write!(f, "- {}: removed field", pointer),
+            Change::NewlyNullable { pointer } => write!(f, "~ {}: newly nullable", pointer),
+            Change::WidenedType { pointer, from, to } => write!(
+                f,
+                "~ {}: widened from {} to {}",
+                pointer,
+                from.join(", "),
+                to.join(", ")
+            ),
+            Change::NewEnumVariants { pointer, values } => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "~ {}: new enum values {}", pointer, values.join(", "))
+            }
+            Change::RequiredToOptional { pointer } => {
+                write!(f, "~ {}: required field is now optional", pointer)
+            }
+        }
+    }
+}
+
+/// The changes from a baseline, as printed by `--drift` and written to `--drift-report`.
+#[derive(Debug, Serialize)]
+pub struct DriftReport {
+    pub baseline: PathBuf,
+    pub changes: Vec<Change>,
+}
+
+impl Display for DriftReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "No drift from {}", self.baseline.display());
+        }
+
+        writeln!(
+            f,
+            "Drift from {}: {} changes",
+            self.baseline.display(),
+            self.changes.len()
+        )?;
+        for change in &self.changes {
+            writeln!(f, "  {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every change from `baseline` to `current`, parents before children.
+pub fn drift(baseline: &Schema, current: &Schema, config: &Config) -> Vec<Change> {
+    let mut changes = Vec::new();
+    compare(baseline, current, String::new(), config, &mut changes);
+    changes
+}
+
+fn compare(
+    baseline: &Schema,
+    current: &Schema,
+    pointer: String,
+    config: &Config,
+    changes: &mut Vec<Change>,
+) {
+    let nullable = |schema: &Schema| schema.type_mask.contains(TypeMask::NULL);
+    if nullable(current) && !nullable(baseline) {
+        changes.push(Change::NewlyNullable {
+            pointer: pointer.clone(),
+        });
+    }
+
+    // Nullability is reported on its own, so it does not widen a type,
+    // and neither do integers where the baseline allows any number
+    let types = |schema: &Schema| (schema.type_mask - TypeMask::NULL).names();
+    let (from, to) = (types(baseline), types(current));
+    let widens = |name: &&str| match *name {
+        "integer" => !from.contains(&"integer") && !from.contains(&"number"),
+        name => !from.contains(&name),
+    };
+    if to.iter().any(widens) {
+        changes.push(Change::WidenedType {
+            pointer: pointer.clone(),
+            from,
+            to,
+        });
+    }
+
+    compare_enums(
+        baseline.string_values.as_ref(),
+        current.string_values.as_ref(),
+        current.type_mask.contains(TypeMask::STRING),
+        "string",
+        |value| value.as_str().into(),
+        &pointer,
+        changes,
+    );
+    compare_enums(
+        baseline.integer_values.as_ref(),
+        current.integer_values.as_ref(),
+        current.type_mask.intersects(TypeMask::I64 | TypeMask::U64),
+        "integer",
+        |&value| integer_value(value),
+        &pointer,
+        changes,
+    );
+
+    // Objects are compared property by property, and a map's values stand in for
+    // every property on its side
+    match (&baseline.object_properties, &current.object_properties) {
+        (Some(baseline_props), Some(current_props)) => {
+            for (key, current_prop) in current_props {
+                let child = format!("{}/{}", pointer, escape(key));
+                match baseline_props.get(key) {
+                    Some(baseline_prop) => {
+                        if baseline_prop.is_required(config.required_threshold)
+                            && !current_prop.is_required(config.required_threshold)
+                        {
+                            changes.push(Change::RequiredToOptional {
+                                pointer: child.clone(),
+                            });
+                        }
+                        compare(baseline_prop, current_prop, child, config, changes);
+                    }
+                    None => changes.push(Change::NewField {
+                        pointer: child,
+                        types: (current_prop.type_mask - TypeMask::ABSENT).names(),
+                    }),
+                }
+            }
+
+            for key in baseline_props.keys() {
+                if !current_props.contains_key(key) {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    changes.push(Change::RemovedField { pointer: child });
+                }
+            }
+        }
+        (Some(baseline_props), None) => {
+            if let Some(current_values) = &current.map_values {
+                for (key, baseline_prop) in baseline_props {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    compare(baseline_prop, current_values, child, config, changes);
+                }
+            }
+        }
+        (None, Some(current_props)) => {
+            if let Some(baseline_values) = &baseline.map_values {
+                for (key, current_prop) in current_props {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    compare(baseline_values, current_prop, child, config, changes);
+                }
+            }
+        }
+        (None, None) => {
+            if let (Some(baseline_values), Some(current_values)) =
+                (&baseline.map_values, &current.map_values)
+            {
+                compare(
+                    baseline_values,
+                    current_values,
+                    format!("{}/*", pointer),
+                    config,
+                    changes,
+                );
+            }
+        }
+    }
+
+    // Likewise, arrays are compared position by position, and the items of an
+    // array without a fixed length stand in for every position
+    if let (Some(baseline_items), Some(current_items)) =
+        (&baseline.array_items, &current.array_items)
+    {
+        compare(
+            baseline_items,
+            current_items,
+            format!("{}/*", pointer),
+            config,
+            changes,
+        );
+    }
+
+    match (&baseline.tuple_items, &current.tuple_items) {
+        (Some(baseline_items), Some(current_items)) => {
+            for (index, (baseline_item, current_item)) in
+                baseline_items.iter().zip(current_items).enumerate()
+            {
+                let child = format!("{}/{}", pointer, index);
+                compare(baseline_item, current_item, child, config, changes);
+            }
+        }
+        (Some(baseline_items), None) => {
+            if let (None, Some(current_items)) = (&baseline.array_items, &current.array_items) {
+                for (index, baseline_item) in baseline_items.iter().enumerate() {
+                    let child = format!("{}/{}", pointer, index);
+                    compare(baseline_item, current_items, child, config, changes);
+                }
+            }
+        }
+        (None, Some(current_items)) => {
+            if let (Some(baseline_items), None) = (&baseline.array_items, &current.array_items) {
+                for (index, current_item) in current_items.iter().enumerate() {
+                    let child = format!("{}/{}", pointer, index);
+                    compare(baseline_items, current_item, child, config, changes);
+                }
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+/// Report values missing from a baseline enum, or an enum that became a plain `name`.
+fn compare_enums<T: Ord>(
+    baseline: Option<&BTreeSet<T>>,
+    current: Option<&BTreeSet<T>>,
+    current_has_type: bool,
+    name: &'static str,
+    to_json: impl Fn(&T) -> serde_json::Value,
+    pointer: &str,
+    changes: &mut Vec<Change>,
+) {
+    let Some(baseline) = baseline else {
+        return;
+    };
+
+    match current {
+        Some(current) => {
+            let values: Vec<_> = current.difference(baseline).map(to_json).collect();
+            if !values.is_empty() {
+                changes.push(Change::NewEnumVariants {
+                    pointer: pointer.to_string(),
+                    values,
+                });
+            }
+        }
+        None if current_has_type => changes.push(Change::WidenedType {
+            pointer: pointer.to_string(),
+            from: vec!["enum"],
+            to: vec![name],
+        }),
+        None => {}
+    }
+}
@@ -0,0 +1,211 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+
+bitflags::bitflags! {
+    /// Each bit indicates a JSON Schema `format` that a string matches.
+    /// Merged by intersection, so a bit stays set only if every string seen matched it.
+    #[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct StringFormat: u32 {
+        const DATE_TIME = 0b0000_0001;
+        const DATE      = 0b0000_0010;
+        const UUID      = 0b0000_0100;
+        const EMAIL     = 0b0000_1000;
+        const IPV4      = 0b0001_0000;
+        const IPV6      = 0b0010_0000;
+        const URI       = 0b0100_0000;
+
+        // Shapes without a standard `format`, used to describe map keys.
+        const INTEGER   = 0b1000_0000;
+        const HASH      = 0b1_0000_0000;
+    }
+}
+
+/// The formats with a JSON Schema `format` name, from most to least specific.
+const FORMAT_NAMES: [(StringFormat, &str); 7] = [
+    (StringFormat::DATE_TIME, "date-time"),
+    (StringFormat::DATE, "date"),
+    (StringFormat::UUID, "uuid"),
+    (StringFormat::EMAIL, "email"),
+    (StringFormat::IPV4, "ipv4"),
+    (StringFormat::IPV6, "ipv6"),
+    (StringFormat::URI, "uri"),
+];
+
+/// The shapes without a `format` name, with the regular expression that describes each.
+const FORMAT_PATTERNS: [(StringFormat, &str); 2] = [
+    (StringFormat::INTEGER, "^[0-9]+$"),
+    (StringFormat::HASH, "^[0-9a-fA-F]{16,}$"),
+];
+
+impl StringFormat {
+    /// Shapes of keys that look like data (identifiers, dates, hashes) rather than field names.
+    pub const DATA_KEYS: StringFormat = StringFormat::UUID
+        .union(StringFormat::INTEGER)
+        .union(StringFormat::DATE)
+        .union(StringFormat::DATE_TIME)
+        .union(StringFormat::HASH);
+
+    /// Classify a string into every format it matches.
+    pub fn detect(value: &str) -> StringFormat {
+        let mut formats = StringFormat::empty();
+        formats.set(StringFormat::DATE_TIME, is_date_time(value));
+        formats.set(StringFormat::DATE, is_date(value));
+        formats.set(StringFormat::UUID, is_uuid(value));
+        formats.set(StringFormat::EMAIL, is_email(value));
+        formats.set(StringFormat::IPV4, value.parse::<Ipv4Addr>().is_ok());
+        formats.set(StringFormat::IPV6, value.parse::<Ipv6Addr>().is_ok());
+        formats.set(StringFormat::URI, is_uri(value));
+        formats.set(StringFormat::INTEGER, is_digits(value.as_bytes()));
+        formats.set(StringFormat::HASH, is_hash(value));
+        formats
+    }
+
+    /// The JSON Schema `format` name of the most specific format set, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        FORMAT_NAMES
+            .into_iter()
+            .find(|(format, _)| self.contains(*format))
+            .map(|(_, name)| name)
+    }
+
+    /// The format with the given JSON Schema `format` name, if it is one that is detected.
+    pub fn from_format_name(name: &str) -> Option<StringFormat> {
+        FORMAT_NAMES
+            .into_iter()
+            .find(|(_, format_name)| *format_name == name)
+            .map(|(format, _)| format)
+    }
+
+    /// A regular expression for shapes that have no `format` name, if any is set.
+    pub fn pattern(&self) -> Option<&'static str> {
+        FORMAT_PATTERNS
+            .into_iter()
+            .find(|(format, _)| self.contains(*format))
+            .map(|(_, pattern)| pattern)
+    }
+
+    /// The shape with the given regular expression, if it is one that [`Self::pattern`] emits.
+    pub fn from_pattern(pattern: &str) -> Option<StringFormat> {
+        FORMAT_PATTERNS
+            .into_iter()
+            .find(|(_, format_pattern)| *format_pattern == pattern)
+            .map(|(format, _)| format)
+    }
+}
+
+fn is_digits(value: &[u8]) -> bool {
+    !value.is_empty() && value.iter().all(u8::is_ascii_digit)
+}
+
+fn parse_number(value: &[u8]) -> Option<u32> {
+    if !is_digits(value) {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// `YYYY-MM-DD` (RFC 3339 `full-date`).
+pub fn is_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' || !is_digits(&bytes[..4]) {
+        return false;
+    }
+
+    match (parse_number(&bytes[5..7]), parse_number(&bytes[8..10])) {
+        (Some(month), Some(day)) => (1..=12).contains(&month) && (1..=31).contains(&day),
+        _ => false,
+    }
+}
+
+/// `HH:MM:SS[.frac]` followed by `Z` or a `+HH:MM` offset (RFC 3339 `full-time`).
+fn is_time(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    if bytes.len() < 9 || bytes[2] != b':' || bytes[5] != b':' {
+        return false;
+    }
+
+    let (hour, minute, second) = (
+        parse_number(&bytes[..2]),
+        parse_number(&bytes[3..5]),
+        parse_number(&bytes[6..8]),
+    );
+    let valid_clock = matches!(
+        (hour, minute, second),
+        (Some(h), Some(m), Some(s)) if h < 24 && m < 60 && s <= 60
+    );
+    if !valid_clock {
+        return false;
+    }
+
+    let mut rest = &bytes[8..];
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return false;
+        }
+        rest = &fraction[digits..];
+    }
+
+    match rest {
+        [b'Z' | b'z'] => true,
+        [b'+' | b'-', h1, h2, b':', m1, m2] => {
+            matches!(parse_number(&[*h1, *h2]), Some(h) if h < 24)
+                && matches!(parse_number(&[*m1, *m2]), Some(m) if m < 60)
+        }
+        _ => false,
+    }
+}
+
+/// RFC 3339 `date-time`, e.g. `2024-01-31T12:00:00Z`.
+pub fn is_date_time(value: &str) -> bool {
+    value.len() > 11
+        && value.is_char_boundary(10)
+        && is_date(&value[..10])
+        && matches!(value.as_bytes()[10], b'T' | b't')
+        && is_time(&value[11..])
+}
+
+/// The canonical 8-4-4-4-12 hexadecimal form.
+pub fn is_uuid(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 36
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            8 | 13 | 18 | 23 => *b == b'-',
+            _ => b.is_ascii_hexdigit(),
+        })
+}
+
+/// At least 16 hexadecimal digits, like the hex digest of a hash.
+pub fn is_hash(value: &str) -> bool {
+    value.len() >= 16 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// A single `@` with a non-empty local part and a dotted domain. Deliberately loose.
+pub fn is_email(value: &str) -> bool {
+    let Some((local, domain)) = value.split_once('@') else {
+        return false;
+    };
+
+    !local.is_empty()
+        && !domain.contains('@')
+        && !value.chars().any(char::is_whitespace)
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-'))
+        && domain.contains('.')
+}
+
+/// A scheme followed by `://` and no whitespace. Bare `scheme:path` URIs are
+/// not matched, as too many ordinary strings look like them.
+pub fn is_uri(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return false;
+    };
+
+    let mut scheme_chars = scheme.chars();
+    scheme_chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme_chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+        && !value.chars().any(|c| c.is_whitespace() || c.is_control())
+}
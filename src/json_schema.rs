@@ -1,8 +1,21 @@
-use std::{collections::HashMap, fmt::Display};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
 
+use indexmap::IndexMap;
 use serde::Serialize;
 
-use crate::schema::{Schema, TypeMask};
+use crate::format::StringFormat;
+use crate::schema::{Config, Schema, TypeMask, Variants};
+
+/// The order in which object properties (and `required`) are emitted and saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PropertyOrder {
+    /// Sorted by key.
+    #[default]
+    Alpha,
+    /// In the order keys were first seen in the input.
+    FirstSeen,
+}
 
 #[derive(Debug)]
 pub enum JsonSchemaType {
@@ -15,6 +28,9 @@ pub enum JsonSchemaType {
     // #[serde(rename = "string")]
     String,
 
+    // #[serde(rename = "integer")]
+    Integer,
+
     // #[serde(rename = "number")]
     Number,
 
@@ -34,6 +50,7 @@ impl Serialize for JsonSchemaType {
             JsonSchemaType::Object => "object",
             JsonSchemaType::Array => "array",
             JsonSchemaType::String => "string",
+            JsonSchemaType::Integer => "integer",
             JsonSchemaType::Number => "number",
             JsonSchemaType::Boolean => "boolean",
             JsonSchemaType::Null => "null",
@@ -42,33 +59,88 @@ impl Serialize for JsonSchemaType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct JsonSchema {
+    // #[serde(rename = "$ref", skip_serializing_if = "Option::is_none", default)]
+    pub reference: Option<String>,
+
     // #[serde(skip_serializing_if = "Option::is_none", default)]
     pub description: Option<String>,
 
     // #[serde(rename = "type", skip_serializing_if = "Vec::is_empty", default)]
     pub schema_type: Vec<JsonSchemaType>,
 
-    // #[serde(skip_serializing_if = "HashMap::is_empty", default)]
-    pub properties: HashMap<String, JsonSchema>,
+    // #[serde(rename = "const", skip_serializing_if = "Option::is_none", default)]
+    pub const_value: Option<serde_json::Value>,
+
+    // #[serde(rename = "enum", skip_serializing_if = "Vec::is_empty", default)]
+    pub enum_values: Vec<serde_json::Value>,
 
-    pub items: Option<Box<JsonSchema>>,
+    // #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub format: Option<String>,
+
+    // #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pattern: Option<String>,
+
+    // #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub minimum: Option<serde_json::Number>,
+
+    // #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub maximum: Option<serde_json::Number>,
+
+    // #[serde(skip_serializing_if = "IndexMap::is_empty", default)]
+    pub properties: IndexMap<String, JsonSchema>,
+
+    // #[serde(rename = "minItems", skip_serializing_if = "Option::is_none", default)]
+    pub min_items: Option<u64>,
+
+    // #[serde(rename = "maxItems", skip_serializing_if = "Option::is_none", default)]
+    pub max_items: Option<u64>,
+
+    // #[serde(rename = "prefixItems", skip_serializing_if = "Vec::is_empty", default)]
+    pub prefix_items: Vec<JsonSchema>,
+
+    pub items: Option<SubSchema>,
+
+    // #[serde(rename = "additionalProperties", skip_serializing_if = "Option::is_none", default)]
+    pub additional_properties: Option<Box<JsonSchema>>,
+
+    // #[serde(rename = "propertyNames", skip_serializing_if = "Option::is_none", default)]
+    pub property_names: Option<Box<JsonSchema>>,
 
     // #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub required: Vec<String>,
 
     // #[serde(skip_serializing_if = "Vec::is_empty", default)]
-    pub any_of: Vec<JsonSchemaVariant>,
+    pub any_of: Vec<JsonSchema>,
+
+    // #[serde(rename = "oneOf", skip_serializing_if = "Vec::is_empty", default)]
+    pub one_of: Vec<JsonSchema>,
+
+    // #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub discriminator: Option<Discriminator>,
+
+    // #[serde(rename = "x-presence", skip_serializing_if = "Option::is_none", default)]
+    pub presence: Option<f64>,
+
+    // #[serde(skip)]
+    /// The name under which this schema is moved to `$defs`, for recursive objects.
+    pub definition: Option<String>,
 }
 
+/// A subschema, or a boolean schema that accepts (`true`) or rejects (`false`) everything.
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
-pub enum JsonSchemaVariant {
-    StringEnum {
-        r#type: JsonSchemaType,
-        r#enum: Vec<String>,
-    },
+pub enum SubSchema {
+    Bool(bool),
+    Schema(Box<JsonSchema>),
+}
+
+/// The OpenAPI `discriminator` of a `oneOf`, naming the property that selects the branch.
+#[derive(Debug, Serialize)]
+pub struct Discriminator {
+    #[serde(rename = "propertyName")]
+    pub property_name: String,
 }
 
 impl Serialize for JsonSchema {
@@ -80,6 +152,10 @@ impl Serialize for JsonSchema {
 
         let mut map = serializer.serialize_map(None)?;
 
+        if let Some(reference) = &self.reference {
+            map.serialize_entry("$ref", reference)?;
+        }
+
         if let Some(description) = &self.description {
             map.serialize_entry("description", description)?;
         }
@@ -93,14 +169,58 @@ impl Serialize for JsonSchema {
             }
         }
 
+        if let Some(const_value) = &self.const_value {
+            map.serialize_entry("const", const_value)?;
+        }
+
+        if !self.enum_values.is_empty() {
+            map.serialize_entry("enum", &self.enum_values)?;
+        }
+
+        if let Some(format) = &self.format {
+            map.serialize_entry("format", format)?;
+        }
+
+        if let Some(pattern) = &self.pattern {
+            map.serialize_entry("pattern", pattern)?;
+        }
+
+        if let Some(minimum) = &self.minimum {
+            map.serialize_entry("minimum", minimum)?;
+        }
+
+        if let Some(maximum) = &self.maximum {
+            map.serialize_entry("maximum", maximum)?;
+        }
+
         if !self.properties.is_empty() {
             map.serialize_entry("properties", &self.properties)?;
         }
 
+        if let Some(min_items) = &self.min_items {
+            map.serialize_entry("minItems", min_items)?;
+        }
+
+        if let Some(max_items) = &self.max_items {
+            map.serialize_entry("maxItems", max_items)?;
+        }
+
+        if !self.prefix_items.is_empty() {
+            map.serialize_entry("prefixItems", &self.prefix_items)?;
+        }
+
         if let Some(items) = &self.items {
             map.serialize_entry("items", items)?;
         }
 
+        if let Some(additional_properties) = &self.additional_properties {
+            map.serialize_entry("additionalProperties", additional_properties)?;
+        }
+
+        if let Some(property_names) = &self.property_names {
+            map.serialize_entry("propertyNames", property_names)?;
+        }
+
         if !self.required.is_empty() {
             map.serialize_entry("required", &self.required)?;
         }
@@ -109,6 +229,18 @@ impl Serialize for JsonSchema {
             map.serialize_entry("anyOf", &self.any_of)?;
         }
 
+        if !self.one_of.is_empty() {
+            map.serialize_entry("oneOf", &self.one_of)?;
+        }
+
+        if let Some(discriminator) = &self.discriminator {
+            map.serialize_entry("discriminator", discriminator)?;
+        }
+
+        if let Some(presence) = &self.presence {
+            map.serialize_entry("x-presence", presence)?;
+        }
+
         map.end()
     }
 }
@@ -119,83 +251,255 @@ pub struct RootJsonSchema {
     pub schema: String,
     #[serde(flatten)]
     pub inner: JsonSchema,
+    #[serde(rename = "$defs", skip_serializing_if = "IndexMap::is_empty")]
+    pub defs: IndexMap<String, JsonSchema>,
 }
 
 impl RootJsonSchema {
-    pub fn new(schema: Schema) -> Self {
-        let json_schema: JsonSchema = schema.into();
-        json_schema.into()
+    pub fn new(mut schema: Schema, config: &Config) -> Self {
+        if config.definitions {
+            schema.fold_recursion(config);
+        }
+
+        let mut result: RootJsonSchema = JsonSchema::new(schema, config).into();
+        if config.definitions {
+            result.inner.lift_definitions(&mut result.defs);
+            result.hoist_repeated();
+        }
+        result
+    }
+
+    /// Move object schemas that appear more than once into `$defs`, replacing each
+    /// occurrence with a `$ref`.
+    fn hoist_repeated(&mut self) {
+        let mut counts = HashMap::new();
+        for schema in std::iter::once(&mut self.inner).chain(self.defs.values_mut()) {
+            for (_, child) in schema.subschemas_mut("") {
+                child.count_repeated(&mut counts);
+            }
+        }
+
+        let mut names: BTreeSet<String> = self.defs.keys().cloned().collect();
+        let mut hoisted = HashMap::new();
+        let mut defs = IndexMap::new();
+        let schemas = std::iter::once(("root", &mut self.inner)).chain(
+            self.defs
+                .iter_mut()
+                .map(|(name, schema)| (name.as_str(), schema)),
+        );
+        for (name, schema) in schemas {
+            for (hint, child) in schema.subschemas_mut(name) {
+                child.hoist_repeated(&hint, &counts, &mut names, &mut hoisted, &mut defs);
+            }
+        }
+        self.defs.extend(defs);
+    }
+}
+
+/// An integer from an integer set as JSON. Sets only hold values that fit in an `i64` or `u64`.
+pub fn integer_value(value: i128) -> serde_json::Value {
+    match u64::try_from(value) {
+        Ok(value) => value.into(),
+        Err(_) => (value as i64).into(),
     }
 }
 
-impl From<Schema> for JsonSchema {
-    fn from(schema: Schema) -> JsonSchema {
+/// A name for a new definition based on `hint`, unique among `names`, which it is added to.
+pub fn definition_name(hint: &str, names: &mut BTreeSet<String>) -> String {
+    let base: String = hint
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+
+    let mut name = base.clone();
+    let mut suffix = 2;
+    while !names.insert(name.clone()) {
+        name = format!("{base}{suffix}");
+        suffix += 1;
+    }
+    name
+}
+
+impl JsonSchema {
+    pub fn new(mut schema: Schema, config: &Config) -> JsonSchema {
+        // A single variant covers every object, so only the merged properties matter
+        if let Some(variants) = schema.variants.take() {
+            if variants.schemas.len() > 1 {
+                return JsonSchema::tagged_union(schema, variants, config);
+            }
+        }
+
+        // Several base types each get a branch with only their own keywords,
+        // though a nullable type stays a type list since `null` has none
+        let base_types: Vec<TypeMask> = BASE_TYPES
+            .iter()
+            .map(|&base_type| schema.type_mask & base_type)
+            .filter(|base_type| !base_type.is_empty())
+            .collect();
+        let by_reference = schema.definition.is_some() || schema.reference.is_some();
+        if base_types
+            .iter()
+            .filter(|&&base_type| by_reference || base_type != TypeMask::NULL)
+            .count()
+            > 1
+        {
+            let any_of = base_types
+                .into_iter()
+                .map(|base_type| JsonSchema::new(take_base_type(&mut schema, base_type), config))
+                .collect();
+            return JsonSchema {
+                any_of,
+                ..Default::default()
+            };
+        }
+
+        if let Some(reference) = schema.reference {
+            return JsonSchema {
+                reference: Some(reference),
+                ..Default::default()
+            };
+        }
+
         let mut result = JsonSchema {
-            description: None,
-            schema_type: vec![],
-            properties: HashMap::new(),
-            items: None,
-            required: Vec::new(),
-            any_of: vec![],
+            definition: schema.definition.take(),
+            ..Default::default()
         };
 
         // Populate the type field based on the schema's type_mask
         if schema.type_mask.contains(TypeMask::ARRAY) {
             result.schema_type.push(JsonSchemaType::Array);
-            if let Some(items) = schema.array_items {
-                let inner: JsonSchema = (*items).into();
-                result.items = Some(Box::new(inner));
+
+            if config.array_lengths {
+                if let Some((min_items, max_items)) = schema.array_lengths {
+                    result.min_items = Some(min_items);
+                    result.max_items = Some(max_items);
+                }
+            }
+
+            if let Some(tuple_items) = schema.tuple_items {
+                // Every array had exactly these positions, so nothing may follow them
+                result.prefix_items = tuple_items
+                    .into_iter()
+                    .map(|item| JsonSchema::new(item, config))
+                    .collect();
+                result.items = Some(SubSchema::Bool(false));
+            } else if let Some(items) = schema.array_items {
+                let inner = JsonSchema::new(*items, config);
+                result.items = Some(SubSchema::Schema(Box::new(inner)));
             }
         }
 
-        if schema.type_mask.contains(TypeMask::STRING) {
+        if schema
+            .type_mask
+            .intersects(TypeMask::STRING | TypeMask::STRING_SET)
+        {
             result.schema_type.push(JsonSchemaType::String);
         }
 
-        if schema.type_mask.contains(TypeMask::I64)
-            || schema.type_mask.contains(TypeMask::U64)
-            || schema.type_mask.contains(TypeMask::F64)
-        {
+        // Integers are only "integer" if no floating point value was ever seen
+        if schema.type_mask.contains(TypeMask::F64) {
             result.schema_type.push(JsonSchemaType::Number);
+        } else if schema.type_mask.intersects(TypeMask::I64 | TypeMask::U64) {
+            result.schema_type.push(JsonSchemaType::Integer);
+
+            // simd-json only produces I64 for negative values, so U64 alone means unsigned
+            if config.integer_format {
+                let format = if schema.type_mask.contains(TypeMask::I64) {
+                    "int64"
+                } else {
+                    "uint64"
+                };
+                result.format = Some(format.to_string());
+            }
+
+            if let Some(values) = schema.integer_values {
+                let values = values.into_iter().map(integer_value).collect();
+                result.restrict_to(values, schema.type_mask);
+            }
+        }
+
+        // String formats are standard keywords, so they win over integer formats
+        if let Some(format) = schema.string_format.as_ref().and_then(StringFormat::name) {
+            result.format = Some(format.to_string());
+        }
+
+        if config.number_ranges {
+            if let Some(range) = &schema.number_range {
+                result.minimum = range.minimum();
+                result.maximum = range.maximum();
+            }
         }
 
         if schema.type_mask.contains(TypeMask::BOOLEAN) {
             result.schema_type.push(JsonSchemaType::Boolean);
+
+            if let Some(value) = schema.boolean_value {
+                result.restrict_to(vec![value.into()], schema.type_mask);
+            }
         }
 
         if schema.type_mask.contains(TypeMask::NULL) {
             result.schema_type.push(JsonSchemaType::Null);
         }
 
-        if schema.type_mask.contains(TypeMask::OBJECT) {
+        if schema
+            .type_mask
+            .intersects(TypeMask::OBJECT | TypeMask::LARGE_OBJ)
+        {
             result.schema_type.push(JsonSchemaType::Object);
         }
 
+        // Large objects are maps: describe their values and, optionally, their keys
         if schema.type_mask.contains(TypeMask::LARGE_OBJ) {
-            result.description = Some("Large object".to_string());
+            match schema.map_values {
+                Some(values) => {
+                    result.additional_properties = Some(Box::new(JsonSchema::new(*values, config)));
+                }
+                None => result.description = Some("Large object".to_string()),
+            }
+
+            if config.property_names {
+                result.property_names = schema.key_format.and_then(|key_format| {
+                    let property_names = JsonSchema {
+                        format: key_format.name().map(str::to_string),
+                        pattern: key_format.pattern().map(str::to_string),
+                        ..Default::default()
+                    };
+                    (property_names.format.is_some() || property_names.pattern.is_some())
+                        .then(|| Box::new(property_names))
+                });
+            }
         }
 
         // Handle object properties
         if let Some(object_properties) = schema.object_properties {
             for (key, value) in object_properties {
-                // If the value is required, add it to the required list
-                if !value.type_mask.contains(TypeMask::ABSENT) {
+                let presence = value.counts.map(|counts| counts.presence());
+
+                // If the value is required, add it to the required list.
+                if value.is_required(config.required_threshold) {
                     result.required.push(key.clone());
                 }
 
                 // Recursively convert nested properties
-                result.properties.insert(key, value.into());
+                let mut property = JsonSchema::new(value, config);
+                if config.presence_annotation {
+                    property.presence = presence;
+                }
+                result.properties.insert(key, property);
             }
         }
 
-        // Handle STRING_SET for anyOf
+        // String sets only allow the values seen
         if schema.type_mask.contains(TypeMask::STRING_SET) {
-            result.schema_type.push(JsonSchemaType::String);
             if let Some(values) = schema.string_values {
-                result.any_of.push(JsonSchemaVariant::StringEnum {
-                    r#type: JsonSchemaType::String,
-                    r#enum: values.into_iter().collect(),
-                });
+                result.restrict_to(
+                    values.into_iter().map(Into::into).collect(),
+                    schema.type_mask,
+                );
             }
         }
 
@@ -203,6 +507,220 @@ impl From<Schema> for JsonSchema {
     }
 }
 
+/// The base types that get their own `anyOf` branch, in the order types are listed.
+const BASE_TYPES: [TypeMask; 6] = [
+    TypeMask::ARRAY,
+    TypeMask::STRING.union(TypeMask::STRING_SET),
+    TypeMask::I64.union(TypeMask::U64).union(TypeMask::F64),
+    TypeMask::BOOLEAN,
+    TypeMask::NULL,
+    TypeMask::OBJECT.union(TypeMask::LARGE_OBJ),
+];
+
+/// Move the parts of `schema` that describe `base_type` into a schema of their own.
+fn take_base_type(schema: &mut Schema, base_type: TypeMask) -> Schema {
+    let mut result = Schema::new(base_type);
+
+    if base_type.contains(TypeMask::ARRAY) {
+        result.array_items = schema.array_items.take();
+        result.array_lengths = schema.array_lengths.take();
+        result.tuple_items = schema.tuple_items.take();
+    }
+
+    if base_type.intersects(TypeMask::STRING | TypeMask::STRING_SET) {
+        result.string_values = schema.string_values.take();
+        result.string_format = schema.string_format.take();
+    }
+
+    if base_type.intersects(TypeMask::I64 | TypeMask::U64 | TypeMask::F64) {
+        result.number_range = schema.number_range.take();
+        result.integer_values = schema.integer_values.take();
+    }
+
+    if base_type.contains(TypeMask::BOOLEAN) {
+        result.boolean_value = schema.boolean_value.take();
+    }
+
+    if base_type.intersects(TypeMask::OBJECT | TypeMask::LARGE_OBJ) {
+        result.object_properties = schema.object_properties.take();
+        result.map_values = schema.map_values.take();
+        result.key_format = schema.key_format.take();
+        result.definition = schema.definition.take();
+        result.reference = schema.reference.take();
+    }
+
+    result
+}
+
+impl JsonSchema {
+    /// Every direct subschema, with a name hint for it: its property name, or else `hint`.
+    fn subschemas_mut(&mut self, hint: &str) -> Vec<(String, &mut JsonSchema)> {
+        let mut result: Vec<(String, &mut JsonSchema)> = self
+            .properties
+            .iter_mut()
+            .map(|(key, property)| (key.clone(), property))
+            .collect();
+
+        let items = match &mut self.items {
+            Some(SubSchema::Schema(items)) => Some(items.as_mut()),
+            _ => None,
+        };
+        result.extend(
+            self.prefix_items
+                .iter_mut()
+                .chain(items)
+                .chain(self.additional_properties.as_deref_mut())
+                .chain(self.any_of.iter_mut())
+                .chain(self.one_of.iter_mut())
+                .map(|child| (hint.to_string(), child)),
+        );
+        result
+    }
+
+    /// Only allow `values`, as `const` if there is a single one. A nullable type also allows `null`.
+    fn restrict_to(&mut self, mut values: Vec<serde_json::Value>, type_mask: TypeMask) {
+        if type_mask.contains(TypeMask::NULL) {
+            values.push(serde_json::Value::Null);
+        }
+
+        if values.len() == 1 {
+            self.const_value = values.pop();
+        } else {
+            self.enum_values = values;
+        }
+    }
+
+    /// Replace this schema with a `$ref`, keeping its presence annotation, and return it.
+    fn replace_with_reference(&mut self, name: &str) -> JsonSchema {
+        let presence = self.presence.take();
+        std::mem::replace(
+            self,
+            JsonSchema {
+                reference: Some(format!("#/$defs/{name}")),
+                presence,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Move every schema marked as a definition into `defs`.
+    fn lift_definitions(&mut self, defs: &mut IndexMap<String, JsonSchema>) {
+        for (_, child) in self.subschemas_mut("") {
+            child.lift_definitions(defs);
+        }
+
+        if let Some(name) = self.definition.take() {
+            let definition = self.replace_with_reference(&name);
+            defs.insert(name, definition);
+        }
+    }
+
+    /// The structure of an object schema, ignoring where it appears, if it could be hoisted.
+    fn repeated_key(&mut self) -> Option<String> {
+        if self.properties.is_empty() || self.reference.is_some() {
+            return None;
+        }
+
+        let presence = self.presence.take();
+        let key = serde_json::to_string(self).ok();
+        self.presence = presence;
+        key
+    }
+
+    fn count_repeated(&mut self, counts: &mut HashMap<String, usize>) {
+        if let Some(key) = self.repeated_key() {
+            *counts.entry(key).or_default() += 1;
+        }
+
+        for (_, child) in self.subschemas_mut("") {
+            child.count_repeated(counts);
+        }
+    }
+
+    fn hoist_repeated(
+        &mut self,
+        hint: &str,
+        counts: &HashMap<String, usize>,
+        names: &mut BTreeSet<String>,
+        hoisted: &mut HashMap<String, String>,
+        defs: &mut IndexMap<String, JsonSchema>,
+    ) {
+        // Keyed by the structure before any nested schemas are hoisted,
+        // which is identical for identical schemas
+        let key = self.repeated_key();
+
+        for (child_hint, child) in self.subschemas_mut(hint) {
+            child.hoist_repeated(&child_hint, counts, names, hoisted, defs);
+        }
+
+        let Some(key) = key.filter(|key| counts[key] > 1) else {
+            return;
+        };
+        match hoisted.get(&key) {
+            Some(name) => {
+                self.replace_with_reference(name);
+            }
+            None => {
+                let name = definition_name(hint, names);
+                let definition = self.replace_with_reference(&name);
+                defs.insert(name.clone(), definition);
+                hoisted.insert(key, name);
+            }
+        }
+    }
+
+    /// A `oneOf` with one object branch per tag value, each pinning the tag with `const`,
+    /// plus a branch for any other types the field can have (such as `null`).
+    /// Properties with unions nested inside are described once, next to the `oneOf`,
+    /// rather than in every branch.
+    fn tagged_union(mut schema: Schema, variants: Variants, config: &Config) -> JsonSchema {
+        let definition = schema.definition.take();
+
+        let properties = schema
+            .object_properties
+            .take()
+            .into_iter()
+            .flatten()
+            .filter(|(key, _)| variants.shared.contains(key))
+            .map(|(key, property)| (key, JsonSchema::new(property, config)))
+            .collect();
+
+        let mut one_of: Vec<JsonSchema> = variants
+            .schemas
+            .into_iter()
+            .map(|(value, variant)| {
+                let mut branch = JsonSchema::new(variant, config);
+                branch.properties.insert(
+                    variants.tag.clone(),
+                    JsonSchema {
+                        const_value: Some(value.into()),
+                        ..Default::default()
+                    },
+                );
+                branch
+            })
+            .collect();
+
+        let rest = Schema {
+            type_mask: schema.type_mask & !(TypeMask::OBJECT | TypeMask::ABSENT),
+            ..schema
+        };
+        if !rest.type_mask.is_empty() {
+            one_of.push(JsonSchema::new(rest, config));
+        }
+
+        JsonSchema {
+            properties,
+            one_of,
+            discriminator: config.openapi_discriminator.then_some(Discriminator {
+                property_name: variants.tag,
+            }),
+            definition,
+            ..Default::default()
+        }
+    }
+}
+
 impl Display for RootJsonSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let json = serde_json::to_string_pretty(self).map_err(|_| std::fmt::Error)?;
@@ -215,6 +733,7 @@ impl From<JsonSchema> for RootJsonSchema {
         RootJsonSchema {
             schema: "https://json-schema.org/draft/2020-12/schema".to_string(),
             inner: val,
+            defs: IndexMap::new(),
         }
     }
-}
\ No newline at end of file
+}
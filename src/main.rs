@@ -1,33 +1,57 @@
 use std::{
-    io::{BufWriter, Write},
+    collections::HashMap,
+    io::{BufReader, BufWriter, IsTerminal, Write},
     path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex},
 };
 
-use anyhow::Result;
-use clap::Parser;
-use json_schema::RootJsonSchema;
-use process::ParallelJsonProcessor;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use compat::CompatMode;
+use drift::DriftReport;
+use json_schema::{PropertyOrder, RootJsonSchema};
+use process::{ErrorPolicy, Location, ParallelJsonProcessor};
+use sample::ArraySampling;
 use schema::{Config, Schema};
+use validate::{ValidationReport, Validator};
 
+pub mod compat;
+pub mod compression;
+pub mod convert;
+pub mod diff;
+pub mod drift;
+pub mod format;
 pub mod json_schema;
 pub mod process;
+pub mod report;
+pub mod sample;
 pub mod schema;
+pub mod validate;
 
-fn process_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<Schema> {
-    let size = std::fs::metadata(path.as_ref())?.len();
+/// The number of malformed lines listed in the `--stats` summary.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+fn process_file<P: AsRef<Path>>(
+    path: P,
+    config: &Config,
+    rejects: Option<Arc<Mutex<BufWriter<std::fs::File>>>>,
+) -> Result<Option<Schema>> {
     let start = std::time::Instant::now();
-    let processer = ParallelJsonProcessor::new(path, config.chunk_size)?;
+    let processer = ParallelJsonProcessor::new(path, config.chunk_size)?
+        .with_error_policy(config.on_error)
+        .with_rejected_lines(rejects.map(|rejects| rejects as Arc<Mutex<dyn Write + Send>>));
 
-    let (processed, schema) = processer.process_with_thread_state(
-        |json, (total, state): &mut (usize, Option<Schema>)| {
+    let ((processed, schema), errors) = processer.process_located_with_thread_state(
+        |json, location, (total, state): &mut (usize, Option<Schema>)| {
             *total += 1;
             match state {
                 Some(schema) => {
-                    let value_type = schema::infer_type(json, config);
+                    let value_type = schema::infer_type(json, config, location.offset);
                     schema.merge(value_type, config);
                 }
                 None => {
-                    *state = Some(schema::infer_type(json, config));
+                    *state = Some(schema::infer_type(json, config, location.offset));
                 }
             }
         },
@@ -38,12 +62,27 @@ fn process_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<Schema> {
             }
             (Some(a), None) => (x + y, Some(a)),
             (None, Some(b)) => (x + y, Some(b)),
-            (None, None) => unreachable!(),
+            (None, None) => (x + y, None),
         },
         || (0, None),
-    );
+    )?;
 
     let elapsed = start.elapsed();
+    let size = processer.size();
+
+    match config.on_error {
+        ErrorPolicy::Skip => {}
+        ErrorPolicy::Warn => {
+            for error in &errors {
+                eprintln!("Warning: skipping malformed line at {}", error);
+            }
+        }
+        ErrorPolicy::Fail => {
+            if let Some(error) = errors.first() {
+                bail!("Malformed line at {}", error);
+            }
+        }
+    }
 
     if config.stats {
         eprintln!(
@@ -60,31 +99,200 @@ fn process_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<Schema> {
             "Throughput: {:.2} records/s",
             processed as f64 / elapsed.as_secs_f64()
         );
+        eprintln!("Skipped {} malformed lines", errors.len());
+        for error in errors.iter().take(MAX_REPORTED_ERRORS) {
+            eprintln!("  {}", error);
+        }
+        if errors.len() > MAX_REPORTED_ERRORS {
+            eprintln!("  ... and {} more", errors.len() - MAX_REPORTED_ERRORS);
+        }
+    }
+
+    Ok(schema)
+}
+
+/// Check every record of the given files against a JSON Schema, printing a summary.
+fn validate(args: ValidateArgs) -> Result<ExitCode> {
+    let schema = serde_json::from_reader(BufReader::new(std::fs::File::open(&args.schema)?))?;
+    let validator = Validator::new(schema)?;
+
+    let mut failed = false;
+    for path in input_files(args.file) {
+        let processor = ParallelJsonProcessor::new(&path, args.chunk_size)?;
+        let (mut report, errors) = processor.process_located_with_thread_state(
+            |json, location, report: &mut ValidationReport| {
+                report.add(validator.validate(json), location, args.samples);
+            },
+            |mut a, b| {
+                a.merge(b, args.samples);
+                a
+            },
+            ValidationReport::default,
+        )?;
+
+        let mut locations: Vec<Location> =
+            report.locations_mut().map(|location| *location).collect();
+        processor.resolve_lines(&mut locations);
+        let lines: HashMap<usize, usize> = locations
+            .into_iter()
+            .map(|location| (location.offset, location.line))
+            .collect();
+        for location in report.locations_mut() {
+            location.line = lines[&location.offset];
+        }
+
+        if report.invalid == 0 {
+            println!("{}: all {} records valid", path.display(), report.records);
+        } else {
+            println!(
+                "{}: {} of {} records invalid",
+                path.display(),
+                report.invalid,
+                report.records
+            );
+        }
+
+        let mut pointers: Vec<_> = report.pointers.into_iter().collect();
+        pointers.sort_by(|(a, a_report), (b, b_report)| {
+            b_report.count.cmp(&a_report.count).then_with(|| a.cmp(b))
+        });
+        for (pointer, pointer_report) in pointers {
+            let pointer = if pointer.is_empty() {
+                "(root)"
+            } else {
+                &pointer
+            };
+            println!("  {}: {} violations", pointer, pointer_report.count);
+            for sample in pointer_report.samples {
+                println!("    line {}: {}", sample.location.line, sample.message);
+            }
+        }
+
+        if !errors.is_empty() {
+            println!("  {} malformed lines", errors.len());
+            for error in errors.iter().take(args.samples) {
+                println!("    {}", error);
+            }
+        }
+
+        failed |= report.invalid > 0 || !errors.is_empty();
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Check whether a new schema is compatible with a baseline, printing every incompatibility.
+fn compat(args: CompatArgs) -> Result<ExitCode> {
+    let baseline = diff::Document::load(&args.baseline)?.into_schema()?;
+    let new = diff::Document::load(&args.new)?.into_schema()?;
+
+    let directions = [
+        (CompatMode::Backward, "Backward", &baseline, &new),
+        (CompatMode::Forward, "Forward", &new, &baseline),
+    ];
+    let mut compatible = true;
+    for (mode, name, reader, writer) in directions {
+        if args.mode != mode && args.mode != CompatMode::Full {
+            continue;
+        }
+
+        let incompatibilities = compat::check(reader, writer);
+        if incompatibilities.is_empty() {
+            println!("{}: compatible", name);
+        } else {
+            println!("{}: {} incompatibilities", name, incompatibilities.len());
+        }
+        for incompatibility in &incompatibilities {
+            println!("  {}", incompatibility);
+        }
+        compatible &= incompatibilities.is_empty();
     }
 
-    Ok(schema.expect("No schema found"))
+    Ok(if compatible {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(2)
+    })
+}
+
+/// The files to read, defaulting to stdin when none are given and it is piped in.
+fn input_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    if files.is_empty() && !std::io::stdin().is_terminal() {
+        vec![PathBuf::from("-")]
+    } else {
+        files
+    }
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
 
+    match args.command {
+        Some(Command::Validate(args)) => return validate(args),
+        Some(Command::Compat(args)) => return compat(args),
+        Some(Command::Diff(args)) => {
+            let a = diff::Document::load(&args.a)?;
+            let b = diff::Document::load(&args.b)?;
+            let differences = diff::diff(&a, &b)?;
+            if differences.is_empty() {
+                println!("No differences");
+            }
+            for difference in differences {
+                println!("{}", difference);
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
     let config = Config {
         max_object_keys: args.max_object_keys,
         max_string_set_values: args.max_string_set_values,
         max_string_set_variant_length: args.max_string_set_variant_length,
         consider_string_set: args.consider_string_set,
+        consider_integer_set: args.consider_integer_set,
+        max_integer_set_values: args.max_integer_set_values,
+        boolean_consts: args.boolean_consts,
         consider_array_items: args.consider_array_items,
         max_array_items: args.max_array_items,
         chunk_size: args.chunk_size,
         stats: args.stats,
+        on_error: args.on_error,
+        integer_format: args.integer_format,
+        number_ranges: args.number_ranges,
+        string_formats: args.string_formats,
+        required_threshold: args.required_threshold,
+        presence_annotation: args.presence_annotation,
+        property_order: args.property_order,
+        property_names: args.property_names,
+        detect_maps: args.detect_maps,
+        tuples: args.tuples,
+        max_tuple_len: args.max_tuple_len,
+        array_lengths: args.array_lengths,
+        array_sampling: args.array_sampling,
+        seed: args.seed,
+        unions: args.unions,
+        discriminators: args.discriminators,
+        max_union_variants: args.max_union_variants,
+        openapi_discriminator: args.openapi_discriminator,
+        definitions: args.definitions,
     };
 
+    // A JSON Schema can be the starting point too, but is never overwritten with a state
+    let mut save_schema = args.schema.clone();
     let mut root_schema: Option<Schema> = match args.schema {
         Some(ref path) => {
             if path.exists() {
                 eprintln!("Loading schema...");
-                let schema = serde_json::from_reader(std::fs::File::open(path)?)?;
-                Some(schema)
+                let document = diff::Document::load(path)?;
+                if let diff::Document::JsonSchema(_) = document {
+                    save_schema = None;
+                }
+                Some(document.into_schema()?)
             } else {
                 None
             }
@@ -92,8 +300,17 @@ fn main() -> Result<()> {
         None => None,
     };
 
-    for path in args.file {
-        let schema = process_file(path, &config)?;
+    let rejects = match args.reject_file {
+        Some(ref path) => Some(Arc::new(Mutex::new(BufWriter::new(std::fs::File::create(
+            path,
+        )?)))),
+        None => None,
+    };
+
+    for path in input_files(args.file) {
+        let Some(schema) = process_file(path, &config, rejects.clone())? else {
+            continue;
+        };
         match root_schema {
             Some(ref mut root_schema) => {
                 eprintln!("Merging schema...");
@@ -103,24 +320,64 @@ fn main() -> Result<()> {
         }
     }
 
+    if let Some(rejects) = rejects {
+        rejects.lock().unwrap().flush()?;
+    }
+
+    // Streamed inputs are merged in no particular order, so sorting is the only
+    // way to make both the output and the saved state reproducible.
+    if config.property_order == PropertyOrder::Alpha {
+        if let Some(root_schema) = &mut root_schema {
+            root_schema.sort_properties();
+        }
+    }
+
     let json_schema = RootJsonSchema::new(
         root_schema
             .clone()
             .expect("No schema found. Did you provide any files?"),
+        &config,
     );
-    
-    match args.output {
-        Some(output) => {
+
+    // In drift mode the summary takes the place of the schema on stdout
+    let drift = match args.drift {
+        Some(path) => {
+            let baseline = diff::Document::load(&path)?.into_schema()?;
+            let changes = drift::drift(&baseline, root_schema.as_ref().unwrap(), &config);
+            Some(DriftReport {
+                baseline: path,
+                changes,
+            })
+        }
+        None => None,
+    };
+
+    match (args.output, &drift) {
+        (Some(output), _) => {
             let mut output = BufWriter::new(std::fs::File::create(output)?);
             output.write_all(json_schema.to_string().as_bytes())?;
         }
-        None => {
+        (None, Some(_)) => {}
+        (None, None) => {
             println!("Generated schema:\n");
             println!("{}", json_schema);
         }
     }
 
-    if let Some(schema) = args.schema {
+    if let Some(drift) = &drift {
+        print!("{}", drift);
+        if let Some(path) = args.drift_report {
+            let output = BufWriter::new(std::fs::File::create(path)?);
+            serde_json::to_writer_pretty(output, drift)?;
+        }
+    }
+
+    if let Some(report) = args.report {
+        let output = BufWriter::new(std::fs::File::create(report)?);
+        serde_json::to_writer_pretty(output, &report::field_report(root_schema.as_ref().unwrap()))?;
+    }
+
+    if let Some(schema) = save_schema {
         eprintln!("Writing schema to file...");
         let mut output = BufWriter::new(std::fs::File::create(schema)?);
         output.write_all(
@@ -130,16 +387,25 @@ fn main() -> Result<()> {
         )?;
     }
 
-    Ok(())
+    // A distinct status, so that drift can be told apart from failure
+    match drift {
+        Some(drift) if !drift.changes.is_empty() => Ok(ExitCode::from(2)),
+        _ => Ok(ExitCode::SUCCESS),
+    }
 }
 
 #[derive(Debug, Parser, Clone)]
 #[command(
     version,
-    long_about = "A tool for inferring JSON schema from NDJSON files."
+    long_about = "A tool for inferring JSON schema from NDJSON files.",
+    args_conflicts_with_subcommands = true
 )]
 struct Args {
-    /// The file(s) to process.
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The file(s) to process. Use `-` to read from stdin.
+    /// If none are given, stdin is read when it is not a terminal.
     file: Vec<PathBuf>,
 
     /// The output file.
@@ -148,14 +414,26 @@ struct Args {
     output: Option<PathBuf>,
 
     /// Path to load or merge an existing schema. If does not exist, a new schema will be created.
-    /// If provided, the schema will be merged with the inferred schema
+    /// If provided, the schema will be merged with the inferred schema.
+    /// A JSON Schema document is converted into a starting point, and is left as it is.
     #[clap(long)]
     schema: Option<PathBuf>,
 
     /// The maximum number of keys in an object before it is considered a large object.
+    /// Large objects are treated as maps, with a single schema for all of their values.
     #[clap(long, default_value = "200")]
     max_object_keys: usize,
 
+    /// Also treat objects as maps when all of their keys look like data (UUIDs, integer IDs,
+    /// dates or hashes) and all of their values have the same type, regardless of size.
+    #[clap(long)]
+    detect_maps: bool,
+
+    /// Emit `propertyNames` for large objects when all of their keys share a shape
+    /// (a format such as `uuid` or `date`, integers, or hex hashes).
+    #[clap(long)]
+    property_names: bool,
+
     /// The maximum number of values in a string set before it is considered just a string.
     #[clap(long = "max-enum-variants", default_value = "100")]
     max_string_set_values: usize,
@@ -168,6 +446,18 @@ struct Args {
     #[clap(long = "enums")]
     consider_string_set: bool,
 
+    /// Whether to consider integer enums (integers with a limited set of values).
+    #[clap(long = "int-enums")]
+    consider_integer_set: bool,
+
+    /// The maximum number of values in an integer set before it is considered just an integer.
+    #[clap(long = "max-int-enum-variants", default_value = "16")]
+    max_integer_set_values: usize,
+
+    /// Whether to emit `const` for booleans that always had the same value.
+    #[clap(long = "bool-consts")]
+    boolean_consts: bool,
+
     /// Whether to consider array items.
     #[clap(long = "array")]
     consider_array_items: bool,
@@ -176,6 +466,49 @@ struct Args {
     #[clap(long = "max-array", default_value = "10")]
     max_array_items: usize,
 
+    /// Which items of arrays longer than `--max-array` to process.
+    #[clap(long = "array-sample", value_enum, default_value = "first")]
+    array_sampling: ArraySampling,
+
+    /// The seed for `--array-sample reservoir`.
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// Emit the shortest and longest arrays seen as `minItems` and `maxItems`.
+    /// Lengths are tracked even without `--array`.
+    #[clap(long = "lengths")]
+    array_lengths: bool,
+
+    /// Whether to infer tuples: arrays that always have the same length get a schema per
+    /// position (`prefixItems`) and reject extra items.
+    #[clap(long)]
+    tuples: bool,
+
+    /// The maximum length of an array to consider as a tuple.
+    #[clap(long = "max-tuple-len", default_value = "8")]
+    max_tuple_len: usize,
+
+    /// Whether to detect tagged unions: objects told apart by a string tag field are kept
+    /// as separate schemas per tag value and emitted as `oneOf`.
+    #[clap(long)]
+    unions: bool,
+
+    /// A property that may tag union variants, in order of preference.
+    #[clap(long = "discriminator", default_values = ["type", "kind"])]
+    discriminators: Vec<String>,
+
+    /// The maximum number of distinct tag values before objects are merged into one schema.
+    #[clap(long, default_value = "16")]
+    max_union_variants: usize,
+
+    /// Emit an OpenAPI `discriminator` alongside `oneOf` for tagged unions.
+    #[clap(long)]
+    openapi_discriminator: bool,
+
+    /// Whether to hoist object schemas that repeat, or recurse into themselves, into `$defs`.
+    #[clap(long = "defs")]
+    definitions: bool,
+
     /// The size of the chunks to read from the file. (Default: 16 MiB)
     #[clap(long, default_value = "16777216")]
     chunk_size: usize,
@@ -183,4 +516,115 @@ struct Args {
     /// Display statistics after processing the file.
     #[clap(long)]
     stats: bool,
+
+    /// Annotate integers with `"format": "int64"` or `"uint64"` depending on whether negative values were seen.
+    #[clap(long = "int-format")]
+    integer_format: bool,
+
+    /// Emit the smallest and largest values seen for numbers as `minimum` and `maximum`.
+    #[clap(long = "ranges")]
+    number_ranges: bool,
+
+    /// Detect string formats (date-time, date, uuid, email, ipv4, ipv6, uri) and emit
+    /// `format` when every string seen for a field matched the same one.
+    #[clap(long = "formats")]
+    string_formats: bool,
+
+    /// Treat fields present in at least this fraction of their parent objects as required (0.0 to 1.0).
+    /// By default a field is only required if it was present in every parent object.
+    #[clap(long, value_parser = parse_ratio)]
+    required_threshold: Option<f64>,
+
+    /// Annotate each property with the fraction of parent objects it was present in, as `x-presence`.
+    #[clap(long = "presence")]
+    presence_annotation: bool,
+
+    /// The order of object properties in the output: sorted by key, or in the order first seen.
+    #[clap(long, value_enum, default_value = "alpha")]
+    property_order: PropertyOrder,
+
+    /// What to do with lines that are not valid JSON.
+    #[clap(long, value_enum, default_value = "skip")]
+    on_error: ErrorPolicy,
+
+    /// Write per-field occurrence and type counts to this file as JSON, keyed by JSON pointer.
+    #[clap(long)]
+    report: Option<PathBuf>,
+
+    /// Compare the inferred schema to the schema state saved at this path (see `--schema`)
+    /// or to a JSON Schema, printing the changes instead of the schema. Exits with status 2 if anything changed.
+    #[clap(long)]
+    drift: Option<PathBuf>,
+
+    /// Write the changes found by `--drift` to this file as JSON.
+    #[clap(long, requires = "drift")]
+    drift_report: Option<PathBuf>,
+
+    /// Write every rejected line to this file as NDJSON, along with its source file and line number.
+    #[clap(long)]
+    reject_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+enum Command {
+    /// Check every record of NDJSON files against a JSON Schema.
+    /// Exits with a non-zero status if any record is invalid or malformed.
+    Validate(ValidateArgs),
+
+    /// Show the structural differences between two schema states (as saved with `--schema`)
+    /// or JSON Schemas, keyed by JSON pointer.
+    Diff(DiffArgs),
+
+    /// Check that a new schema is compatible with a baseline before promoting its producer.
+    /// Exits with status 2 if it is not.
+    Compat(CompatArgs),
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct CompatArgs {
+    /// The baseline schema state, as saved with `--schema`, or a JSON Schema.
+    baseline: PathBuf,
+
+    /// The new schema state, or a JSON Schema.
+    new: PathBuf,
+
+    /// Which direction to check: `backward` (consumers of the baseline can read new data),
+    /// `forward` (consumers of the new schema can read baseline data), or `full` (both).
+    #[clap(long, value_enum, default_value_t)]
+    mode: CompatMode,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct DiffArgs {
+    /// The old schema.
+    a: PathBuf,
+
+    /// The new schema.
+    b: PathBuf,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+struct ValidateArgs {
+    /// The JSON Schema (Draft 2020-12) to validate against.
+    schema: PathBuf,
+
+    /// The file(s) to validate. Use `-` to read from stdin.
+    /// If none are given, stdin is read when it is not a terminal.
+    file: Vec<PathBuf>,
+
+    /// The number of offending lines to show for each JSON pointer.
+    #[clap(long, default_value = "3")]
+    samples: usize,
+
+    /// The size of the chunks to read from the file. (Default: 16 MiB)
+    #[clap(long, default_value = "16777216")]
+    chunk_size: usize,
+}
+
+fn parse_ratio(value: &str) -> Result<f64> {
+    let ratio: f64 = value.parse()?;
+    if !(0.0..=1.0).contains(&ratio) {
+        bail!("must be between 0.0 and 1.0");
+    }
+    Ok(ratio)
 }
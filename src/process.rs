@@ -1,30 +1,131 @@
 use indicatif::ProgressBar;
 use memmap2::Mmap;
+use rayon::iter::{Either, ParallelBridge};
 use rayon::prelude::*;
+use serde::Serialize;
 use simd_json::{to_borrowed_value, BorrowedValue};
+use std::borrow::Cow;
+use std::fmt::Display;
 use std::fs::File;
-use std::io::Result;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::compression::Compression;
+
+/// What to do with lines that cannot be parsed as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorPolicy {
+    /// Skip malformed lines, only reporting them with `--stats`.
+    #[default]
+    Skip,
+    /// Skip malformed lines and print a warning for each one.
+    Warn,
+    /// Stop processing and exit with an error on the first malformed line.
+    Fail,
+}
+
+/// A line that could not be parsed as JSON.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    /// The file the line was read from.
+    pub path: PathBuf,
+    /// The 1-based line number within the file.
+    pub line: usize,
+    /// The byte offset of the start of the line within the file.
+    pub offset: usize,
+    /// The error reported by simd-json.
+    pub message: String,
+}
+
+/// A rejected line as written to the `--reject-file`, one JSON object per line.
+#[derive(Debug, Serialize)]
+pub struct RejectedLine<'a> {
+    pub file: &'a Path,
+    pub line: usize,
+    pub offset: usize,
+    pub error: &'a str,
+    pub record: Cow<'a, str>,
+}
+
+/// Where a parsed value was found in the input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    /// The byte offset of the start of the line within the file.
+    pub offset: usize,
+    /// The 1-based line number within the file. For memory mapped files this is 0
+    /// until filled in by [`ParallelJsonProcessor::resolve_lines`].
+    pub line: usize,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} (byte {}): {}",
+            self.path.display(),
+            self.line,
+            self.offset,
+            self.message
+        )
+    }
+}
+
+/// Where a `ParallelJsonProcessor` reads its input from.
+enum Source {
+    /// A regular file, memory mapped and split into chunks up front.
+    Mmap(Mmap),
+    /// Any other reader (stdin, pipes, compressed files, ...), split into chunks as it is read.
+    Stream(Mutex<Option<Box<dyn Read + Send>>>),
+}
+
+/// A newline-aligned piece of the input, processed as a unit by one thread.
+struct Chunk<'a> {
+    data: Cow<'a, [u8]>,
+    /// The byte offset of the chunk within the input.
+    offset: usize,
+    /// The line number of the first line in the chunk, if known while reading.
+    first_line: Option<usize>,
+}
 
 /// A type for processing JSON files in parallel using simd-json and user-provided logic.
 pub struct ParallelJsonProcessor {
-    mmap: Mmap,
-    file_size: usize,
+    path: PathBuf,
+    source: Source,
     chunk_size: usize,
 
+    error_policy: ErrorPolicy,
+    /// Where to write rejected lines as they are found, shared between files.
+    rejects: Option<Arc<Mutex<dyn Write + Send>>>,
+    aborted: AtomicBool,
+
+    /// Bytes read so far from a `Source::Stream`, after decompression.
+    stream_bytes: AtomicU64,
+    /// The first I/O error hit while reading a `Source::Stream` or writing rejected lines.
+    io_error: Mutex<Option<std::io::Error>>,
+
     progress: ProgressBar,
 }
 
 impl ParallelJsonProcessor {
     /// Create a new `ParallelJsonProcessor` from a file.
+    /// Regular files are memory mapped, unless they are compressed; `-` reads
+    /// from stdin, and any other non-regular file (such as a named pipe) is streamed.
     pub fn new<P: AsRef<Path>>(path: P, chunk_size: usize) -> Result<Self> {
-        // let path = PathBuf::from(filename);
-        let file = File::open(path)?;
-        let file_size = file.metadata()?.len() as usize;
+        let path = path.as_ref().to_path_buf();
 
-        eprintln!("Mapping file of size: {}", file_size);
+        if path.as_os_str() == "-" {
+            return Self::from_reader(path, std::io::stdin(), chunk_size);
+        }
 
-        let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+        let file = File::open(&path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Self::from_reader(path, file, chunk_size);
+        }
+
+        let file_size = metadata.len() as usize;
 
         let progress = ProgressBar::new(file_size as u64);
         progress.set_style(
@@ -34,36 +135,138 @@ impl ParallelJsonProcessor {
                 .progress_chars("#>-"),
         );
 
-        Ok(Self {
-            mmap,
-            file_size,
+        let mut reader = BufReader::new(file);
+        if let Some(compression) = Compression::detect(reader.fill_buf()?) {
+            eprintln!("Decompressing {} file of size: {}", compression, file_size);
+            return Self::stream(path, reader, chunk_size, progress);
+        }
+
+        eprintln!("Mapping file of size: {}", file_size);
+
+        let mmap = unsafe { memmap2::MmapOptions::new().map(reader.get_ref())? };
+
+        Ok(Self::with_source(
+            path,
+            Source::Mmap(mmap),
             chunk_size,
             progress,
-        })
+        ))
+    }
+
+    /// Create a new `ParallelJsonProcessor` that streams NDJSON from `reader`,
+    /// decompressing it if needed. `path` is used to label errors.
+    pub fn from_reader<P, R>(path: P, reader: R, chunk_size: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        R: Read + Send + 'static,
+    {
+        eprintln!("Streaming from: {}", path.as_ref().display());
+
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(
+            indicatif::ProgressStyle::default_spinner()
+                .template("[{elapsed_precise}] {spinner} {bytes} ({bytes_per_sec})")
+                .unwrap(),
+        );
+
+        Self::stream(
+            path.as_ref().to_path_buf(),
+            BufReader::new(reader),
+            chunk_size,
+            progress,
+        )
+    }
+
+    /// Stream from `reader`, decompressing it if its contents are compressed.
+    /// Progress is reported in bytes read from `reader`, before decompression.
+    fn stream<R>(
+        path: PathBuf,
+        mut reader: BufReader<R>,
+        chunk_size: usize,
+        progress: ProgressBar,
+    ) -> Result<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        let compression = Compression::detect(reader.fill_buf()?);
+        let reader = progress.wrap_read(reader);
+        let reader: Box<dyn Read + Send> = match compression {
+            Some(compression) => compression.decoder(reader)?,
+            None => Box::new(reader),
+        };
+
+        Ok(Self::with_source(
+            path,
+            Source::Stream(Mutex::new(Some(reader))),
+            chunk_size,
+            progress,
+        ))
+    }
+
+    fn with_source(
+        path: PathBuf,
+        source: Source,
+        chunk_size: usize,
+        progress: ProgressBar,
+    ) -> Self {
+        Self {
+            path,
+            source,
+            chunk_size,
+            error_policy: ErrorPolicy::default(),
+            rejects: None,
+            aborted: AtomicBool::new(false),
+            stream_bytes: AtomicU64::new(0),
+            io_error: Mutex::new(None),
+            progress,
+        }
+    }
+
+    /// Set how lines that fail to parse are handled.
+    /// With `ErrorPolicy::Fail`, processing stops at the first malformed line.
+    pub fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Write each line that fails to parse to `rejects` as a [`RejectedLine`], as soon as it
+    /// is found, so that their contents are never held in memory. Lines are written in the
+    /// order they are found, which is not necessarily their order in the input.
+    pub fn with_rejected_lines(mut self, rejects: Option<Arc<Mutex<dyn Write + Send>>>) -> Self {
+        self.rejects = rejects;
+        self
+    }
+
+    /// The number of bytes of input: the file size when memory mapped,
+    /// or the (decompressed) bytes read so far when streaming.
+    pub fn size(&self) -> u64 {
+        match &self.source {
+            Source::Mmap(mmap) => mmap.len() as u64,
+            Source::Stream(_) => self.stream_bytes.load(Ordering::Relaxed),
+        }
     }
 
     /// Find chunk boundaries based on newlines.
-    fn find_chunk_boundaries(&self) -> Vec<(usize, usize)> {
+    fn find_chunk_boundaries(&self, mmap: &Mmap) -> Vec<(usize, usize)> {
+        let file_size = mmap.len();
         let mut boundaries = Vec::new();
         let mut current_start = 0;
 
-        while current_start < self.file_size {
-            let tentative_end = current_start
-                .saturating_add(self.chunk_size)
-                .min(self.file_size);
+        while current_start < file_size {
+            let tentative_end = current_start.saturating_add(self.chunk_size).min(file_size);
 
-            if tentative_end >= self.file_size {
-                boundaries.push((current_start, self.file_size));
+            if tentative_end >= file_size {
+                boundaries.push((current_start, file_size));
                 break;
             }
 
             let mut actual_end = tentative_end;
-            while actual_end < self.file_size && self.mmap[actual_end] != b'\n' {
+            while actual_end < file_size && mmap[actual_end] != b'\n' {
                 actual_end += 1;
             }
 
-            if actual_end >= self.file_size {
-                boundaries.push((current_start, self.file_size));
+            if actual_end >= file_size {
+                boundaries.push((current_start, file_size));
                 break;
             } else {
                 boundaries.push((current_start, actual_end + 1));
@@ -74,89 +277,351 @@ impl ParallelJsonProcessor {
         boundaries
     }
 
+    /// Split the input into chunks that can be processed in parallel.
+    fn chunks(&self) -> impl ParallelIterator<Item = Chunk<'_>> {
+        match &self.source {
+            Source::Mmap(mmap) => {
+                let boundaries = self.find_chunk_boundaries(mmap);
+                // Rejected lines are written as they are found, so they need their line
+                // numbers up front: count the lines of every chunk, in parallel
+                let first_lines: Vec<Option<usize>> = match self.rejects {
+                    Some(_) => {
+                        let line_counts: Vec<usize> = boundaries
+                            .par_iter()
+                            .map(|&(start, end)| {
+                                mmap[start..end].iter().filter(|&&b| b == b'\n').count()
+                            })
+                            .collect();
+                        line_counts
+                            .into_iter()
+                            .scan(1, |line, count| {
+                                let first_line = *line;
+                                *line += count;
+                                Some(Some(first_line))
+                            })
+                            .collect()
+                    }
+                    None => vec![None; boundaries.len()],
+                };
+                Either::Left(boundaries.into_par_iter().zip(first_lines).map(
+                    |((start, end), first_line)| Chunk {
+                        data: Cow::Borrowed(&mmap[start..end]),
+                        offset: start,
+                        first_line,
+                    },
+                ))
+            }
+            Source::Stream(reader) => {
+                let reader = reader
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .unwrap_or_else(|| Box::new(std::io::empty()));
+                Either::Right(
+                    StreamChunks {
+                        reader: BufReader::new(reader),
+                        chunk_size: self.chunk_size,
+                        offset: 0,
+                        line: 1,
+                        processor: self,
+                    }
+                    .par_bridge(),
+                )
+            }
+        }
+    }
+
     /// Process the JSON file in parallel using a user-provided closure.
     /// The closure processes a single JSON object (BorrowedValue) and returns a result.
-    pub fn process<F, T, R>(&self, processor: F, reducer: R) -> T
+    /// Lines that fail to parse are returned alongside the result, ordered by position.
+    pub fn process<F, T, R>(&self, processor: F, reducer: R) -> Result<(T, Vec<ParseError>)>
     where
         F: Fn(&BorrowedValue) -> T + Sync + Send,
         R: Fn(T, T) -> T + Sync + Send + Copy,
         T: Send + Sync + Default,
     {
-        let chunk_boundaries = self.find_chunk_boundaries();
-        chunk_boundaries
-            .into_par_iter()
-            .map(|(start, end)| {
-                self.process_chunk(&self.mmap[start..end], &processor)
+        let (result, mut errors) = self
+            .chunks()
+            .map(|chunk| {
+                let mut errors = Vec::new();
+                let result = self
+                    .process_chunk(&chunk, &processor, &mut errors)
                     .into_iter()
-                    .fold(Default::default(), reducer)
+                    .fold(Default::default(), reducer);
+                (result, errors)
             })
-            .reduce(Default::default, reducer)
+            .reduce(Default::default, |(a, mut a_errors), (b, b_errors)| {
+                a_errors.extend(b_errors);
+                (reducer(a, b), a_errors)
+            });
+
+        self.progress.finish();
+        self.finish_errors(&mut errors)?;
+
+        Ok((result, errors))
     }
 
     /// Process a single chunk of JSON data.
-    fn process_chunk<F, T>(&self, chunk: &[u8], processor: &F) -> Vec<T>
+    fn process_chunk<F, T>(
+        &self,
+        chunk: &Chunk,
+        processor: &F,
+        errors: &mut Vec<ParseError>,
+    ) -> Vec<T>
     where
         F: Fn(&BorrowedValue) -> T,
     {
         let mut results = Vec::new();
-        for line in chunk.split(|&b| b == b'\n') {
-            if line.is_empty() {
-                continue;
-            }
-
-            // Parse the line into a BorrowedValue using simd-json.
-            let mut owned_line = line.to_vec();
-            let borrowed_value = to_borrowed_value(&mut owned_line);
-            if let Ok(parsed) = borrowed_value {
-                results.push(processor(&parsed));
-            }
-        }
+        self.for_each_value(chunk, errors, |parsed, _| {
+            results.push(processor(parsed));
+        });
         results
     }
 
+    /// Process the JSON file in parallel, folding values into per-thread state.
+    /// Lines that fail to parse are returned alongside the state, ordered by position.
     pub fn process_with_thread_state<F, R, S>(
         &self,
         processor: F,
         reducer: R,
         state_initializer: impl Fn() -> S,
-    ) -> S
+    ) -> Result<(S, Vec<ParseError>)>
     where
         F: Fn(&BorrowedValue, &mut S) + Sync + Send,
         R: Fn(S, S) -> S + Sync + Send,
         S: Default + Clone + Send,
     {
-        let chunk_boundaries = self.find_chunk_boundaries();
+        self.process_located_with_thread_state(
+            |value, _, state| processor(value, state),
+            reducer,
+            state_initializer,
+        )
+    }
 
-        let result = chunk_boundaries
-            .into_par_iter()
-            .fold_with(state_initializer(), |mut local_state, (start, end)| {
-                self.process_chunk_with_state(&self.mmap[start..end], &processor, &mut local_state);
-                local_state
-            })
-            .reduce(Default::default, reducer);
+    /// Like [`Self::process_with_thread_state`], but also tells the closure where each value
+    /// was found, so that it can point back at individual lines.
+    pub fn process_located_with_thread_state<F, R, S>(
+        &self,
+        processor: F,
+        reducer: R,
+        state_initializer: impl Fn() -> S,
+    ) -> Result<(S, Vec<ParseError>)>
+    where
+        F: Fn(&BorrowedValue, Location, &mut S) + Sync + Send,
+        R: Fn(S, S) -> S + Sync + Send,
+        S: Default + Clone + Send,
+    {
+        let (result, mut errors) = self
+            .chunks()
+            .fold_with(
+                (state_initializer(), Vec::new()),
+                |(mut local_state, mut errors), chunk| {
+                    self.process_chunk_with_state(
+                        &chunk,
+                        &processor,
+                        &mut local_state,
+                        &mut errors,
+                    );
+                    (local_state, errors)
+                },
+            )
+            .reduce(Default::default, |(a, mut a_errors), (b, b_errors)| {
+                a_errors.extend(b_errors);
+                (reducer(a, b), a_errors)
+            });
 
         self.progress.finish();
+        self.finish_errors(&mut errors)?;
+
+        Ok((result, errors))
+    }
 
-        result
+    fn process_chunk_with_state<F, S>(
+        &self,
+        chunk: &Chunk,
+        processor: &F,
+        state: &mut S,
+        errors: &mut Vec<ParseError>,
+    ) where
+        F: Fn(&BorrowedValue, Location, &mut S),
+    {
+        self.for_each_value(chunk, errors, |parsed, location| {
+            processor(parsed, location, state)
+        });
     }
 
-    fn process_chunk_with_state<F, S>(&self, chunk: &[u8], processor: &F, state: &mut S)
+    /// Parse each line of a chunk, passing parsed values to `f` and recording
+    /// lines that fail to parse in `errors`.
+    fn for_each_value<F>(&self, chunk: &Chunk, errors: &mut Vec<ParseError>, mut f: F)
     where
-        F: Fn(&BorrowedValue, &mut S),
+        F: FnMut(&BorrowedValue, Location),
     {
-        for line in chunk.split(|&b| b == b'\n') {
+        // Streams report progress as they are read.
+        let track_progress = matches!(self.source, Source::Mmap(_));
+
+        let mut line_offset = chunk.offset;
+        for (index, line) in chunk.data.split(|&b| b == b'\n').enumerate() {
+            let line_start = line_offset;
+            line_offset += line.len() + 1;
+
             if line.is_empty() {
                 continue;
             }
 
+            if self.aborted.load(Ordering::Relaxed) {
+                return;
+            }
+
             // Update progress bar
-            self.progress.inc(line.len() as u64);
+            if track_progress {
+                self.progress.inc(line.len() as u64);
+            }
+
+            // For memory mapped files the line is filled in by `resolve_line_numbers`.
+            let location = Location {
+                offset: line_start,
+                line: chunk.first_line.map_or(0, |first_line| first_line + index),
+            };
 
             let mut owned_line = line.to_vec();
-            let borrowed_value = to_borrowed_value(&mut owned_line);
-            if let Ok(parsed) = borrowed_value {
-                processor(&parsed, state);
-            }
+            match to_borrowed_value(&mut owned_line) {
+                Ok(parsed) => f(&parsed, location),
+                Err(error) => {
+                    let error = ParseError {
+                        path: self.path.clone(),
+                        line: location.line,
+                        offset: location.offset,
+                        message: error.to_string(),
+                    };
+                    if let Some(rejects) = &self.rejects {
+                        self.write_rejected(rejects, &error, line);
+                    }
+                    errors.push(error);
+
+                    if self.error_policy == ErrorPolicy::Fail {
+                        self.aborted.store(true, Ordering::Relaxed);
+                    }
+                }
+            };
         }
     }
+
+    /// Write a rejected line, stopping on the first write error.
+    fn write_rejected(&self, rejects: &Mutex<dyn Write + Send>, error: &ParseError, line: &[u8]) {
+        let rejected = RejectedLine {
+            file: &error.path,
+            line: error.line,
+            offset: error.offset,
+            error: &error.message,
+            record: String::from_utf8_lossy(line),
+        };
+        let mut rejects = rejects.lock().unwrap();
+        let result = serde_json::to_writer(&mut *rejects, &rejected)
+            .map_err(std::io::Error::from)
+            .and_then(|()| rejects.write_all(b"\n"));
+        if let Err(write_error) = result {
+            self.io_error.lock().unwrap().get_or_insert(write_error);
+            self.aborted.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Surface any I/O error, then sort `errors` by position and fill in their line numbers.
+    fn finish_errors(&self, errors: &mut [ParseError]) -> Result<()> {
+        if let Some(error) = self.io_error.lock().unwrap().take() {
+            return Err(error);
+        }
+
+        // Chunks already know their first line when rejected lines are written
+        errors.sort_by_key(|error| error.offset);
+        if let (Source::Mmap(mmap), None) = (&self.source, &self.rejects) {
+            let lines = errors
+                .iter_mut()
+                .map(|error| (error.offset, &mut error.line));
+            Self::resolve_line_numbers(mmap, lines);
+        }
+
+        Ok(())
+    }
+
+    /// Sort `locations` by position and fill in their line numbers, if not known yet.
+    pub fn resolve_lines(&self, locations: &mut [Location]) {
+        locations.sort();
+        if let Source::Mmap(mmap) = &self.source {
+            let lines = locations
+                .iter_mut()
+                .map(|location| (location.offset, &mut location.line));
+            Self::resolve_line_numbers(mmap, lines);
+        }
+    }
+
+    /// Compute line numbers for sorted offsets in a memory mapped file.
+    /// Newlines are only counted up to the last offset, so this is free when there are none.
+    fn resolve_line_numbers<'a>(mmap: &Mmap, lines: impl Iterator<Item = (usize, &'a mut usize)>) {
+        let mut line = 1;
+        let mut position = 0;
+        for (offset, line_number) in lines {
+            line += mmap[position..offset]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            position = offset;
+            *line_number = line;
+        }
+    }
+}
+
+/// Reads newline-aligned chunks of roughly `chunk_size` bytes from a stream,
+/// tracking the offset and line number of each so errors can be located.
+struct StreamChunks<'a, R> {
+    reader: R,
+    chunk_size: usize,
+    offset: usize,
+    line: usize,
+    processor: &'a ParallelJsonProcessor,
+}
+
+impl<'a, R: BufRead> Iterator for StreamChunks<'a, R> {
+    type Item = Chunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.processor.aborted.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity(self.chunk_size);
+        let result = (&mut self.reader)
+            .take(self.chunk_size as u64)
+            .read_to_end(&mut data)
+            .and_then(|_| match data.last() {
+                // Finish the last line so that no line spans two chunks.
+                Some(&last) if last != b'\n' => self.reader.read_until(b'\n', &mut data),
+                _ => Ok(0),
+            });
+
+        if let Err(error) = result {
+            // Decoders report corrupt input without saying which input it was
+            let path = self.processor.path.display();
+            let error = std::io::Error::new(error.kind(), format!("{}: {}", path, error));
+            *self.processor.io_error.lock().unwrap() = Some(error);
+            return None;
+        }
+
+        if data.is_empty() {
+            return None;
+        }
+
+        let chunk = Chunk {
+            offset: self.offset,
+            first_line: Some(self.line),
+            data: Cow::Owned(data),
+        };
+
+        self.offset += chunk.data.len();
+        self.line += chunk.data.iter().filter(|&&b| b == b'\n').count();
+        self.processor
+            .stream_bytes
+            .fetch_add(chunk.data.len() as u64, Ordering::Relaxed);
+
+        Some(chunk)
+    }
 }
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::schema::{Schema, TypeCounts};
+
+/// Occurrence statistics for a single field, as written to the `--report` file.
+#[derive(Debug, Serialize)]
+pub struct FieldReport {
+    /// The number of times the field could have appeared.
+    pub seen: u64,
+    /// The number of times the field was present.
+    pub present: u64,
+    /// `present / seen`.
+    pub presence: f64,
+    /// The fraction of present values that were `null`.
+    pub null_ratio: f64,
+    /// The number of values seen of each base type.
+    pub types: TypeCounts,
+    /// The shortest and longest arrays seen, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub array_lengths: Option<(u64, u64)>,
+}
+
+/// Build a report of every field in `schema` that has counts, keyed by JSON pointer.
+/// Array items and the values of large objects are addressed with a `*` segment, e.g. `/tags/*`,
+/// and tuple positions by index, e.g. `/point/0`.
+pub fn field_report(schema: &Schema) -> BTreeMap<String, FieldReport> {
+    let mut report = BTreeMap::new();
+    collect(schema, String::new(), &mut report);
+    report
+}
+
+fn collect(schema: &Schema, path: String, report: &mut BTreeMap<String, FieldReport>) {
+    if let Some(properties) = &schema.object_properties {
+        for (key, property) in properties {
+            collect(property, format!("{}/{}", path, escape(key)), report);
+        }
+    }
+
+    if let Some(items) = &schema.array_items {
+        collect(items, format!("{}/*", path), report);
+    }
+
+    for (index, item) in schema.tuple_items.iter().flatten().enumerate() {
+        collect(item, format!("{}/{}", path, index), report);
+    }
+
+    if let Some(values) = &schema.map_values {
+        collect(values, format!("{}/*", path), report);
+    }
+
+    if let Some(counts) = schema.counts {
+        report.insert(
+            path,
+            FieldReport {
+                seen: counts.seen,
+                present: counts.present,
+                presence: counts.presence(),
+                null_ratio: ratio(counts.types.null, counts.present),
+                types: counts.types,
+                array_lengths: schema.array_lengths,
+            },
+        );
+    }
+}
+
+/// Escape a property name for use as a JSON pointer segment.
+pub fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn ratio(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
@@ -0,0 +1,62 @@
+/// How to pick which elements of a long array are used to infer its item schema.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArraySampling {
+    /// The first N elements.
+    #[default]
+    First,
+    /// N elements evenly spaced across the array.
+    Stride,
+    /// N elements chosen uniformly at random, reproducible with `--seed`.
+    Reservoir,
+    /// The last N elements.
+    Last,
+}
+
+/// Pick `count` indices out of `0..len`, in increasing order.
+/// Returns every index when the array is not longer than `count`.
+/// `record` identifies the record the array belongs to, so that random samples vary between records.
+pub fn sample_indices(
+    len: usize,
+    count: usize,
+    sampling: ArraySampling,
+    seed: u64,
+    record: u64,
+) -> Vec<usize> {
+    if len <= count {
+        return (0..len).collect();
+    }
+
+    match sampling {
+        ArraySampling::First => (0..count).collect(),
+        ArraySampling::Last => (len - count..len).collect(),
+        ArraySampling::Stride => (0..count).map(|i| i * len / count).collect(),
+        ArraySampling::Reservoir => {
+            // Floyd's algorithm: a uniform sample in O(count) rather than O(len).
+            // Seeding by record and length, rather than from shared state, keeps the
+            // choice independent of how records are split across threads.
+            let record = SplitMix64(record).next();
+            let mut rng =
+                SplitMix64(seed ^ record ^ (len as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let mut indices = Vec::with_capacity(count);
+            for j in len - count..len {
+                let t = (rng.next() % (j as u64 + 1)) as usize;
+                indices.push(if indices.contains(&t) { j } else { t });
+            }
+            indices.sort_unstable();
+            indices
+        }
+    }
+}
+
+/// A small, fast, seedable pseudo-random number generator.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -1,11 +1,17 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     hash::Hash,
 };
 
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use simd_json::{BorrowedValue, StaticNode};
 
+use crate::format::StringFormat;
+use crate::json_schema::{definition_name, PropertyOrder};
+use crate::process::ErrorPolicy;
+use crate::sample::{sample_indices, ArraySampling};
+
 // const MAX_OBJECT_KEYS: usize = 200;
 // const MAX_STRING_SET_VALUES: usize = 100;
 // const MAX_STRING_SET_VARIANT_LENGTH: usize = 50;
@@ -19,17 +25,39 @@ pub struct Config {
     pub max_string_set_values: usize,
     pub max_string_set_variant_length: usize,
     pub consider_string_set: bool,
+    pub consider_integer_set: bool,
+    pub max_integer_set_values: usize,
+    pub boolean_consts: bool,
     pub consider_array_items: bool,
     pub max_array_items: usize,
     pub chunk_size: usize,
     pub stats: bool,
+    pub on_error: ErrorPolicy,
+    pub integer_format: bool,
+    pub number_ranges: bool,
+    pub string_formats: bool,
+    pub required_threshold: Option<f64>,
+    pub presence_annotation: bool,
+    pub property_order: PropertyOrder,
+    pub property_names: bool,
+    pub detect_maps: bool,
+    pub tuples: bool,
+    pub max_tuple_len: usize,
+    pub array_lengths: bool,
+    pub array_sampling: ArraySampling,
+    pub seed: u64,
+    pub unions: bool,
+    pub discriminators: Vec<String>,
+    pub max_union_variants: usize,
+    pub openapi_discriminator: bool,
+    pub definitions: bool,
 }
 
 bitflags::bitflags! {
     /// Each bit indicates presence of a certain "base" type.
     /// E.g. STRING | NULL means "Either(String, Null)".
 
-    #[derive(Clone, Debug, Copy, Serialize, Deserialize)]
+    #[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
     pub struct TypeMask: u32 {
         const STRING     = 0b0000_0000_0001;
         const BOOLEAN    = 0b0000_0000_0010;
@@ -55,6 +83,26 @@ bitflags::bitflags! {
     }
 }
 
+impl TypeMask {
+    /// The JSON Schema type names of the base types in this mask, in the order they are emitted.
+    /// Integers are named separately from other numbers.
+    pub fn names(self) -> Vec<&'static str> {
+        [
+            (TypeMask::ARRAY, "array"),
+            (TypeMask::STRING | TypeMask::STRING_SET, "string"),
+            (TypeMask::I64 | TypeMask::U64, "integer"),
+            (TypeMask::F64, "number"),
+            (TypeMask::BOOLEAN, "boolean"),
+            (TypeMask::NULL, "null"),
+            (TypeMask::OBJECT | TypeMask::LARGE_OBJ, "object"),
+        ]
+        .into_iter()
+        .filter(|(bits, _)| self.intersects(*bits))
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
 /// A unified schema node that can represent multiple primitive types
 /// plus an optional object structure. The "Either" concept is stored
 /// in `type_mask` as multiple bits set. "Optional" is just `NULL` bit set
@@ -65,55 +113,332 @@ pub struct Schema {
     pub type_mask: TypeMask,
 
     /// If `type_mask` includes "object", then `object_properties` is `Some(...)`.
-    /// Otherwise `None`.
+    /// Otherwise `None`, or where a converted contract recurs, since its shape is unknown there.
     // pub object_properties: Option<BTreeMap<String, Schema>>,
-    pub object_properties: Option<HashMap<String, Schema>>,
+    /// Properties are kept in the order they were first seen.
+    pub object_properties: Option<IndexMap<String, Schema>>,
 
     // If `type_mask` includes "string_set", then `string_values` is `Some(...)`.
-    pub string_values: Option<HashSet<String>>,
+    pub string_values: Option<BTreeSet<String>>,
 
     // / If `type_mask` includes "array" and you need deeper array validation
     // / (like "array of X"), you could store that schema here.
     pub array_items: Option<Box<Schema>>,
+
+    /// If `type_mask` includes a number type, the smallest and largest values seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub number_range: Option<NumberRange>,
+
+    /// If `type_mask` includes an integer type and integer sets are being detected,
+    /// every integer seen, unless there were too many.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integer_values: Option<BTreeSet<i128>>,
+
+    /// If `type_mask` includes "boolean" and boolean constants are being detected,
+    /// the only boolean value seen, unless both were.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boolean_value: Option<bool>,
+
+    /// If `type_mask` includes a string type and formats are being detected,
+    /// the formats that every string seen matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub string_format: Option<StringFormat>,
+
+    /// If `type_mask` includes "array", the shortest and longest lengths seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub array_lengths: Option<(u64, u64)>,
+
+    /// If `type_mask` includes "array" and every array seen had the same (short) length,
+    /// the merged schema of each position.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tuple_items: Option<Vec<Schema>>,
+
+    /// If `type_mask` includes "object" and every object seen carried the same tag field,
+    /// a separate object schema per tag value, alongside the merged `object_properties`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variants: Option<Variants>,
+
+    /// If `type_mask` includes "large object", the merged schema of all its values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map_values: Option<Box<Schema>>,
+
+    /// If `type_mask` includes "large object", the shapes that every key matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_format: Option<StringFormat>,
+
+    /// How often this node was seen, and with which types.
+    /// `None` if any of the merged schemas were loaded from a state without counts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counts: Option<Counts>,
+
+    /// Set by [`Schema::fold_recursion`] on a recursive object: the name of its definition.
+    #[serde(skip)]
+    pub definition: Option<String>,
+
+    /// Set by [`Schema::fold_recursion`] where a recursive object recurs: a `$ref` back to it.
+    /// Only the type mask and counts of such a node are kept.
+    #[serde(skip)]
+    pub reference: Option<String>,
+}
+
+/// A step from a schema to one of its children.
+#[derive(Clone, Debug)]
+enum Step {
+    Property(String),
+    Items,
+    Tuple(usize),
+    Values,
+}
+
+/// The object schemas of a tagged (discriminated) union.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Variants {
+    /// The property whose string value tells the variants apart, e.g. `type`.
+    pub tag: String,
+    /// The object schema for each value of the tag.
+    pub schemas: BTreeMap<String, Schema>,
+    /// Properties with unions nested inside, which are only described in full by the
+    /// merged properties. Each variant keeps just their types and counts, or every level
+    /// of nesting would multiply the size of the schema.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub shared: BTreeSet<String>,
+}
+
+impl Variants {
+    /// Reduce the shared properties of every variant to their types and counts.
+    fn share(&mut self) {
+        for variant in self.schemas.values_mut() {
+            for (key, property) in variant.object_properties.iter_mut().flatten() {
+                if self.shared.contains(key) {
+                    *property = property.shallow();
+                }
+            }
+        }
+    }
+}
+
+/// Occurrence counts for a schema node, merged by addition.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Counts {
+    /// The number of times this node could have appeared: the number of parent
+    /// objects for a property, or the number of values otherwise.
+    pub seen: u64,
+    /// The number of times a value was actually present.
+    pub present: u64,
+    /// The number of values seen of each base type.
+    pub types: TypeCounts,
+}
+
+/// The number of values seen of each base type. String sets count as strings.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct TypeCounts {
+    pub string: u64,
+    pub boolean: u64,
+    pub null: u64,
+    pub array: u64,
+    pub object: u64,
+    pub i64: u64,
+    pub u64: u64,
+    pub f64: u64,
+}
+
+impl Counts {
+    /// Counts for a single value with the given type.
+    pub fn new(mask: TypeMask) -> Self {
+        let count = |bits: TypeMask| mask.intersects(bits) as u64;
+        Counts {
+            seen: 1,
+            present: 1,
+            types: TypeCounts {
+                string: count(TypeMask::STRING | TypeMask::STRING_SET),
+                boolean: count(TypeMask::BOOLEAN),
+                null: count(TypeMask::NULL),
+                array: count(TypeMask::ARRAY),
+                object: count(TypeMask::OBJECT | TypeMask::LARGE_OBJ),
+                i64: count(TypeMask::I64),
+                u64: count(TypeMask::U64),
+                f64: count(TypeMask::F64),
+            },
+        }
+    }
+
+    /// The fraction of times this node was present when it could have been.
+    pub fn presence(&self) -> f64 {
+        if self.seen == 0 {
+            0.0
+        } else {
+            self.present as f64 / self.seen as f64
+        }
+    }
+
+    pub fn merge(&mut self, other: Counts) {
+        self.seen += other.seen;
+        self.present += other.present;
+
+        let (a, b) = (&mut self.types, other.types);
+        a.string += b.string;
+        a.boolean += b.boolean;
+        a.null += b.null;
+        a.array += b.array;
+        a.object += b.object;
+        a.i64 += b.i64;
+        a.u64 += b.u64;
+        a.f64 += b.f64;
+    }
+}
+
+/// The smallest and largest values seen for each kind of number, kept separately
+/// so that integers stay exact.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct NumberRange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub i64: Option<(i64, i64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub u64: Option<(u64, u64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f64: Option<(f64, f64)>,
+}
+
+impl NumberRange {
+    pub fn merge(&mut self, other: NumberRange) {
+        fn merge_bounds<T: PartialOrd + Copy>(a: &mut Option<(T, T)>, b: Option<(T, T)>) {
+            *a = match (*a, b) {
+                (Some((a_min, a_max)), Some((b_min, b_max))) => Some((
+                    if b_min < a_min { b_min } else { a_min },
+                    if b_max > a_max { b_max } else { a_max },
+                )),
+                (a, b) => a.or(b),
+            };
+        }
+
+        merge_bounds(&mut self.i64, other.i64);
+        merge_bounds(&mut self.u64, other.u64);
+        merge_bounds(&mut self.f64, other.f64);
+    }
+
+    /// The smallest value seen across all kinds of number.
+    pub fn minimum(&self) -> Option<serde_json::Number> {
+        // Negative integers are always I64 and non-negative ones U64,
+        // so only one of them can hold the smallest integer.
+        let integer = self
+            .i64
+            .map(|(min, _)| serde_json::Number::from(min))
+            .or_else(|| self.u64.map(|(min, _)| serde_json::Number::from(min)));
+        Self::pick(integer, self.f64.map(|(min, _)| min), |a, b| a < b)
+    }
+
+    /// The largest value seen across all kinds of number.
+    pub fn maximum(&self) -> Option<serde_json::Number> {
+        let integer = self
+            .u64
+            .map(|(_, max)| serde_json::Number::from(max))
+            .or_else(|| self.i64.map(|(_, max)| serde_json::Number::from(max)));
+        Self::pick(integer, self.f64.map(|(_, max)| max), |a, b| a > b)
+    }
+
+    /// Choose between the integer and floating point bound, keeping the integer
+    /// exact unless the float is strictly `better`.
+    fn pick(
+        integer: Option<serde_json::Number>,
+        float: Option<f64>,
+        better: impl Fn(f64, f64) -> bool,
+    ) -> Option<serde_json::Number> {
+        match (integer, float.and_then(serde_json::Number::from_f64)) {
+            (Some(integer), Some(float)) => {
+                if better(float.as_f64()?, integer.as_f64()?) {
+                    Some(float)
+                } else {
+                    Some(integer)
+                }
+            }
+            (integer, float) => integer.or(float),
+        }
+    }
+}
+
+/// Infer the schema of `value`, part of the record at byte offset `record` in its file.
+/// The offset only varies which elements `--array-sample reservoir` picks between records.
+#[inline]
+pub fn infer_type(value: &BorrowedValue, config: &Config, record: usize) -> Schema {
+    let mut schema = infer_value(value, config, record);
+    schema.counts = Some(Counts::new(schema.type_mask));
+    schema
 }
 
 #[inline]
-pub fn infer_type(value: &BorrowedValue, config: &Config) -> Schema {
+fn infer_value(value: &BorrowedValue, config: &Config, record: usize) -> Schema {
     match value {
         BorrowedValue::Static(static_node) => match static_node {
-            StaticNode::I64(_) => Schema::new(TypeMask::I64),
-            StaticNode::U64(_) => Schema::new(TypeMask::U64),
-            StaticNode::F64(_) => Schema::new(TypeMask::F64),
-            StaticNode::Bool(_) => Schema::new(TypeMask::BOOLEAN),
+            StaticNode::I64(value) => Schema {
+                number_range: Some(NumberRange {
+                    i64: Some((*value, *value)),
+                    ..Default::default()
+                }),
+                integer_values: config
+                    .consider_integer_set
+                    .then(|| BTreeSet::from([*value as i128])),
+                ..Schema::new(TypeMask::I64)
+            },
+            StaticNode::U64(value) => Schema {
+                number_range: Some(NumberRange {
+                    u64: Some((*value, *value)),
+                    ..Default::default()
+                }),
+                integer_values: config
+                    .consider_integer_set
+                    .then(|| BTreeSet::from([*value as i128])),
+                ..Schema::new(TypeMask::U64)
+            },
+            StaticNode::F64(value) => Schema {
+                number_range: Some(NumberRange {
+                    f64: Some((*value, *value)),
+                    ..Default::default()
+                }),
+                ..Schema::new(TypeMask::F64)
+            },
+            StaticNode::Bool(value) => Schema {
+                boolean_value: config.boolean_consts.then_some(*value),
+                ..Schema::new(TypeMask::BOOLEAN)
+            },
             StaticNode::Null => Schema::new(TypeMask::NULL),
         },
         BorrowedValue::String(value) => {
-            // if we're not considering string sets, just return a string
-            if !config.consider_string_set {
-                return Schema::new(TypeMask::STRING);
-            }
+            let string_format = config.string_formats.then(|| StringFormat::detect(value));
 
+            // if we're not considering string sets, just return a string
             // if the string is too long don't bother with a set
-            if value.len() > config.max_string_set_variant_length {
-                return Schema::new(TypeMask::STRING);
+            if !config.consider_string_set || value.len() > config.max_string_set_variant_length {
+                return Schema {
+                    string_format,
+                    ..Schema::new(TypeMask::STRING)
+                };
             }
 
             // otherwise, add it to the set
-            let mut set = HashSet::new();
+            let mut set = BTreeSet::new();
             set.insert(value.to_string());
             Schema {
-                type_mask: TypeMask::STRING_SET,
-                object_properties: None,
                 string_values: Some(set),
-                array_items: None,
+                string_format,
+                ..Schema::new(TypeMask::STRING_SET)
             }
         }
         BorrowedValue::Array(arr) => {
+            let mut schema = Schema::new(TypeMask::ARRAY);
+            schema.array_lengths = Some((arr.len() as u64, arr.len() as u64));
+
+            if config.tuples && arr.len() <= config.max_tuple_len {
+                schema.tuple_items = Some(
+                    arr.iter()
+                        .map(|element| infer_type(element, config, record))
+                        .collect(),
+                );
+            }
+
             if !config.consider_array_items {
-                return Schema::new(TypeMask::ARRAY);
+                return schema;
             }
 
-            let mut schema = Schema::new(TypeMask::ARRAY);
             let mut item_schema: Option<Schema> = None;
 
             // if the array is too long, don't bother with items
@@ -121,8 +446,15 @@ pub fn infer_type(value: &BorrowedValue, config: &Config) -> Schema {
             //     return schema;
             // }
 
-            for element in arr.iter().take(config.max_array_items) {
-                let element_schema = infer_type(element, config);
+            let indices = sample_indices(
+                arr.len(),
+                config.max_array_items,
+                config.array_sampling,
+                config.seed,
+                record as u64,
+            );
+            for element in indices.into_iter().map(|index| &arr[index]) {
+                let element_schema = infer_type(element, config, record);
                 match &mut item_schema {
                     Some(existing) => existing.merge(element_schema, config),
                     None => item_schema = Some(element_schema),
@@ -132,17 +464,69 @@ pub fn infer_type(value: &BorrowedValue, config: &Config) -> Schema {
             schema.array_items = item_schema.map(Box::new);
             schema
         }
-        BorrowedValue::Object(inner) => Schema {
-            type_mask: TypeMask::OBJECT,
-            object_properties: Some(
-                inner
-                    .iter()
-                    .map(|(key, value)| (key.to_string(), infer_type(value, config)))
-                    .collect(),
-            ),
-            string_values: None,
-            array_items: None,
-        },
+        BorrowedValue::Object(inner) => {
+            let mut schema = Schema {
+                object_properties: Some(
+                    inner
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), infer_type(value, config, record)))
+                        .collect(),
+                ),
+                ..Schema::new(TypeMask::OBJECT)
+            };
+
+            if inner.len() > config.max_object_keys
+                || (config.detect_maps && schema.looks_like_map())
+            {
+                schema.convert_to_map(config);
+            } else if config.unions {
+                // the first tag field present with a short string value picks the variant
+                let tagged =
+                    config
+                        .discriminators
+                        .iter()
+                        .find_map(|tag| match inner.get(tag.as_str()) {
+                            Some(BorrowedValue::String(value))
+                                if value.len() <= config.max_string_set_variant_length =>
+                            {
+                                Some((tag, value))
+                            }
+                            _ => None,
+                        });
+
+                if let Some((tag, value)) = tagged {
+                    let properties = schema.object_properties.iter().flatten();
+                    let shared: BTreeSet<String> = properties
+                        .clone()
+                        .filter(|(_, property)| property.has_unions())
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    let variant = Schema {
+                        object_properties: Some(
+                            properties
+                                .map(|(key, property)| {
+                                    let property = if shared.contains(key) {
+                                        property.shallow()
+                                    } else {
+                                        property.clone()
+                                    };
+                                    (key.clone(), property)
+                                })
+                                .collect(),
+                        ),
+                        counts: Some(Counts::new(TypeMask::OBJECT)),
+                        ..Schema::new(TypeMask::OBJECT)
+                    };
+                    schema.variants = Some(Variants {
+                        tag: tag.clone(),
+                        schemas: BTreeMap::from([(value.to_string(), variant)]),
+                        shared,
+                    });
+                }
+            }
+
+            schema
+        }
     }
 }
 
@@ -153,44 +537,417 @@ impl Schema {
             object_properties: None,
             string_values: None,
             array_items: None,
+            number_range: None,
+            integer_values: None,
+            boolean_value: None,
+            string_format: None,
+            array_lengths: None,
+            tuple_items: None,
+            variants: None,
+            map_values: None,
+            key_format: None,
+            counts: None,
+            definition: None,
+            reference: None,
         }
     }
 
-    pub fn merge(&mut self, other: Schema, config: &Config) {
-        // Special case for string sets (if enabled)
-        if config.consider_string_set {
-            if self.type_mask.contains(TypeMask::STRING_SET)
-                && other.type_mask.contains(TypeMask::STRING)
-                || self.type_mask.contains(TypeMask::STRING)
-                    && other.type_mask.contains(TypeMask::STRING_SET)
-            {
-                self.type_mask &= !TypeMask::STRING_SET;
-                self.type_mask |= TypeMask::STRING;
-                self.string_values = None;
-            } else if self.type_mask.contains(TypeMask::STRING_SET)
-                && other.type_mask.contains(TypeMask::STRING_SET)
-            {
-                let mut string_values_set = std::mem::take(&mut self.string_values);
-                if let Some(self_values) = &mut string_values_set {
-                    if let Some(other_values) = other.string_values {
-                        if self_values.len() + other_values.len() > config.max_string_set_values {
-                            self.type_mask &= !TypeMask::STRING_SET;
-                            self.type_mask |= TypeMask::STRING;
-                            self.string_values = None;
-                            // return;
-                        } else {
-                            self_values.extend(other_values);
+    /// The direct children of this schema, except union variants.
+    fn children(&self) -> Vec<(Step, &Schema)> {
+        let properties = self.object_properties.iter().flatten();
+        let tuple_items = self.tuple_items.iter().flatten().enumerate();
+        properties
+            .map(|(key, property)| (Step::Property(key.clone()), property))
+            .chain(
+                self.array_items
+                    .as_deref()
+                    .map(|items| (Step::Items, items)),
+            )
+            .chain(tuple_items.map(|(index, item)| (Step::Tuple(index), item)))
+            .chain(
+                self.map_values
+                    .as_deref()
+                    .map(|values| (Step::Values, values)),
+            )
+            .collect()
+    }
+
+    fn child_mut(&mut self, step: &Step) -> Option<&mut Schema> {
+        match step {
+            Step::Property(key) => self.object_properties.as_mut()?.get_mut(key),
+            Step::Items => self.array_items.as_deref_mut(),
+            Step::Tuple(index) => self.tuple_items.as_mut()?.get_mut(*index),
+            Step::Values => self.map_values.as_deref_mut(),
+        }
+    }
+
+    /// The shortest path to a descendant object with exactly the same keys as this one,
+    /// each with the same base types (give or take `null`, which recursion often ends in).
+    /// A tagged union is never folded, as its copies would be mixed into every variant.
+    fn recursion_path(&self) -> Option<Vec<Step>> {
+        let keys = self.object_properties.as_ref()?;
+        if keys.len() < 2 || self.variants.is_some() {
+            return None;
+        }
+
+        let mut queue: VecDeque<(Vec<Step>, &Schema)> = self
+            .children()
+            .into_iter()
+            .map(|(step, child)| (vec![step], child))
+            .collect();
+        while let Some((path, node)) = queue.pop_front() {
+            if node.reference.is_some() {
+                continue;
+            }
+
+            if let Some(properties) = &node.object_properties {
+                if properties.len() == keys.len()
+                    && properties.iter().all(|(key, property)| {
+                        keys.get(key)
+                            .is_some_and(|own| own.same_base_types(property))
+                    })
+                {
+                    return Some(path);
+                }
+            }
+
+            for (step, child) in node.children() {
+                let mut path = path.clone();
+                path.push(step);
+                queue.push_back((path, child));
+            }
+        }
+
+        None
+    }
+
+    /// Whether both schemas allow the same base types, not counting `null`,
+    /// or either only allows `null`.
+    fn same_base_types(&self, other: &Schema) -> bool {
+        let base_types = |schema: &Schema| (schema.type_mask - TypeMask::NULL).names();
+        let (own, other) = (base_types(self), base_types(other));
+        own.is_empty() || other.is_empty() || own == other
+    }
+
+    /// Replace the node at `path` with a reference carrying only its type mask and counts,
+    /// returning the node.
+    fn replace_at(&mut self, path: &[Step], reference: &str) -> Option<Schema> {
+        let node = path
+            .iter()
+            .try_fold(self, |node, step| node.child_mut(step))?;
+        let placeholder = Schema {
+            counts: node.counts,
+            reference: Some(reference.to_string()),
+            ..Schema::new(node.type_mask)
+        };
+        Some(std::mem::replace(node, placeholder))
+    }
+
+    /// Fold recursive structures, such as comments with replies, into a single schema.
+    ///
+    /// An object that contains an object with exactly the same keys, of the same types,
+    /// absorbs every copy of itself along that path, however deep, and each copy is
+    /// replaced by a reference back.
+    pub fn fold_recursion(&mut self, config: &Config) {
+        self.fold_recursion_named(None, &mut BTreeSet::new(), config);
+    }
+
+    fn fold_recursion_named(
+        &mut self,
+        name: Option<&str>,
+        names: &mut BTreeSet<String>,
+        config: &Config,
+    ) {
+        while let Some(path) = self.recursion_path() {
+            // The root is referenced as the whole document
+            let reference = match (name, &self.definition) {
+                (None, _) => "#".to_string(),
+                (Some(_), Some(definition)) => format!("#/$defs/{definition}"),
+                (Some(name), None) => {
+                    let definition = definition_name(name, names);
+                    let reference = format!("#/$defs/{definition}");
+                    self.definition = Some(definition);
+                    reference
+                }
+            };
+
+            let mut copies = Vec::new();
+            let mut next = self.replace_at(&path, &reference);
+            while let Some(mut copy) = next {
+                next = copy.replace_at(&path, &reference);
+                copies.push(copy);
+            }
+
+            // Only the object part of each copy is this object; how often it appeared,
+            // and as what else, stays with the reference
+            let counts = self.counts;
+            for mut copy in copies {
+                copy.type_mask &= TypeMask::OBJECT | TypeMask::LARGE_OBJ;
+                if !copy.type_mask.is_empty() {
+                    self.merge(copy, config);
+                }
+            }
+            self.counts = counts;
+        }
+
+        let name = name.unwrap_or("root");
+        if let Some(properties) = &mut self.object_properties {
+            for (key, property) in properties {
+                property.fold_recursion_named(Some(key), names, config);
+            }
+        }
+
+        for child in self
+            .array_items
+            .iter_mut()
+            .map(Box::as_mut)
+            .chain(self.tuple_items.iter_mut().flatten())
+            .chain(self.map_values.iter_mut().map(Box::as_mut))
+        {
+            child.fold_recursion_named(Some(name), names, config);
+        }
+
+        // A variant is only ever emitted as a branch of its union, pinned to its tag
+        // value, so only its properties can be folded
+        for variant in self
+            .variants
+            .iter_mut()
+            .flat_map(|variants| variants.schemas.values_mut())
+        {
+            for (key, property) in variant.object_properties.iter_mut().flatten() {
+                property.fold_recursion_named(Some(key), names, config);
+            }
+        }
+    }
+
+    /// A copy of this schema without any children, keeping only its own types and counts.
+    fn shallow(&self) -> Schema {
+        Schema {
+            string_values: self.string_values.clone(),
+            number_range: self.number_range,
+            integer_values: self.integer_values.clone(),
+            boolean_value: self.boolean_value,
+            string_format: self.string_format,
+            array_lengths: self.array_lengths,
+            key_format: self.key_format,
+            counts: self.counts,
+            ..Schema::new(self.type_mask)
+        }
+    }
+
+    /// Whether this schema or any descendant is a tagged union.
+    pub fn has_unions(&self) -> bool {
+        self.variants.is_some()
+            || self
+                .children()
+                .into_iter()
+                .any(|(_, child)| child.has_unions())
+    }
+
+    /// Recursively sort object properties by key.
+    pub fn sort_properties(&mut self) {
+        if let Some(properties) = &mut self.object_properties {
+            properties.sort_unstable_keys();
+            for property in properties.values_mut() {
+                property.sort_properties();
+            }
+        }
+
+        if let Some(items) = &mut self.array_items {
+            items.sort_properties();
+        }
+
+        for item in self.tuple_items.iter_mut().flatten() {
+            item.sort_properties();
+        }
+
+        for variant in self
+            .variants
+            .iter_mut()
+            .flat_map(|variants| variants.schemas.values_mut())
+        {
+            variant.sort_properties();
+        }
+
+        if let Some(values) = &mut self.map_values {
+            values.sort_properties();
+        }
+    }
+
+    /// Whether this property is required: always present in its parent objects or,
+    /// with a threshold, almost always.
+    pub fn is_required(&self, threshold: Option<f64>) -> bool {
+        let presence = self.counts.map(|counts| counts.presence());
+        !self.type_mask.contains(TypeMask::ABSENT)
+            || matches!(
+                (threshold, presence),
+                (Some(threshold), Some(presence)) if presence >= threshold
+            )
+    }
+
+    /// Record that this property was missing from `objects` parent objects.
+    fn add_unseen(&mut self, objects: Option<u64>) {
+        match (&mut self.counts, objects) {
+            (Some(counts), Some(objects)) => counts.seen += objects,
+            _ => self.counts = None,
+        }
+    }
+
+    pub fn merge(&mut self, mut other: Schema, config: &Config) {
+        // A tagged union only survives while every object on both sides carries the
+        // same tag field, with few enough distinct values. Otherwise both sides fall
+        // back to their merged properties.
+        let objects = TypeMask::OBJECT | TypeMask::LARGE_OBJ;
+        let keeps_union = match (&self.variants, &other.variants) {
+            (Some(self_variants), Some(other_variants)) => {
+                let new_values = other_variants
+                    .schemas
+                    .keys()
+                    .filter(|value| !self_variants.schemas.contains_key(*value))
+                    .count();
+                self_variants.tag == other_variants.tag
+                    && self_variants.schemas.len() + new_values <= config.max_union_variants
+            }
+            (Some(_), None) => !other.type_mask.intersects(objects),
+            (None, Some(_)) => !self.type_mask.intersects(objects),
+            (None, None) => true,
+        };
+        if !keeps_union {
+            self.variants = None;
+            other.variants = None;
+        }
+
+        // The number of objects on each side, before merging, tells a property
+        // missing from one side how many more times it could have appeared.
+        let self_objects = self.counts.map(|counts| counts.types.object);
+        let other_objects = other.counts.map(|counts| counts.types.object);
+        // A side without any objects (only nulls or scalars), or with objects of unknown
+        // shape (where a converted contract recurs), says nothing about which properties
+        // are present
+        let has_objects = |schema: &Schema, objects: Option<u64>| {
+            schema.object_properties.is_some()
+                && objects.map_or(schema.type_mask.contains(TypeMask::OBJECT), |objects| {
+                    objects > 0
+                })
+        };
+        let self_has_objects = has_objects(self, self_objects);
+        let other_has_objects = has_objects(&other, other_objects);
+
+        match (&mut self.counts, other.counts) {
+            (Some(self_counts), Some(other_counts)) => self_counts.merge(other_counts),
+            _ => self.counts = None,
+        }
+
+        // A format only survives if every string on both sides matched it.
+        // Strings seen without format detection mean nothing can be said.
+        let strings = TypeMask::STRING | TypeMask::STRING_SET;
+        match (&mut self.string_format, other.string_format) {
+            (Some(self_format), Some(other_format)) => *self_format &= other_format,
+            (Some(_), None) if other.type_mask.intersects(strings) => self.string_format = None,
+            (None, Some(other_format)) if !self.type_mask.intersects(strings) => {
+                self.string_format = Some(other_format)
+            }
+            _ => {}
+        }
+
+        // Likewise, a tuple only survives if every array on both sides had the same length
+        match (&mut self.tuple_items, other.tuple_items) {
+            (Some(self_items), Some(other_items)) if self_items.len() == other_items.len() => {
+                for (self_item, other_item) in self_items.iter_mut().zip(other_items) {
+                    self_item.merge(other_item, config);
+                }
+            }
+            (Some(_), None) if !other.type_mask.contains(TypeMask::ARRAY) => {}
+            (None, Some(other_items)) if !self.type_mask.contains(TypeMask::ARRAY) => {
+                self.tuple_items = Some(other_items)
+            }
+            _ => self.tuple_items = None,
+        }
+
+        // And the variants of a surviving tagged union are merged by tag value
+        match (&mut self.variants, other.variants) {
+            (Some(self_variants), Some(other_variants)) => {
+                for (value, other_variant) in other_variants.schemas {
+                    match self_variants.schemas.get_mut(&value) {
+                        Some(self_variant) => self_variant.merge(other_variant, config),
+                        None => {
+                            self_variants.schemas.insert(value, other_variant);
                         }
                     }
-                } else {
-                    self.string_values = other.string_values;
                 }
-                self.string_values = string_values_set;
-            } else {
-                self.type_mask |= other.type_mask;
+                self_variants.shared.extend(other_variants.shared);
+                self_variants.share();
+            }
+            (None, Some(other_variants)) => self.variants = Some(other_variants),
+            _ => {}
+        }
+
+        // Integer sets and boolean constants likewise only survive while every value on
+        // both sides was recorded, and there are few enough of them
+        let integers = TypeMask::I64 | TypeMask::U64;
+        match (&mut self.integer_values, other.integer_values) {
+            (Some(self_values), Some(other_values)) => {
+                self_values.extend(other_values);
+                if self_values.len() > config.max_integer_set_values {
+                    self.integer_values = None;
+                }
+            }
+            (Some(_), None) if !other.type_mask.intersects(integers) => {}
+            (None, Some(other_values)) if !self.type_mask.intersects(integers) => {
+                self.integer_values = Some(other_values)
+            }
+            _ => self.integer_values = None,
+        }
+
+        match (self.boolean_value, other.boolean_value) {
+            (Some(self_value), Some(other_value)) if self_value == other_value => {}
+            (Some(_), None) if !other.type_mask.contains(TypeMask::BOOLEAN) => {}
+            (None, Some(other_value)) if !self.type_mask.contains(TypeMask::BOOLEAN) => {
+                self.boolean_value = Some(other_value)
+            }
+            _ => self.boolean_value = None,
+        }
+
+        // Ranges only survive while every number on both sides was recorded
+        let numbers = TypeMask::I64 | TypeMask::U64 | TypeMask::F64;
+        match (&mut self.number_range, other.number_range) {
+            (Some(self_range), Some(other_range)) => self_range.merge(other_range),
+            (Some(_), None) if !other.type_mask.intersects(numbers) => {}
+            (None, Some(other_range)) if !self.type_mask.intersects(numbers) => {
+                self.number_range = Some(other_range)
+            }
+            _ => self.number_range = None,
+        }
+
+        // And so do array lengths, for arrays
+        self.array_lengths = match (self.array_lengths, other.array_lengths) {
+            (Some((self_min, self_max)), Some((other_min, other_max))) => {
+                Some((self_min.min(other_min), self_max.max(other_max)))
+            }
+            (Some(lengths), None) if !other.type_mask.contains(TypeMask::ARRAY) => Some(lengths),
+            (None, Some(lengths)) if !self.type_mask.contains(TypeMask::ARRAY) => Some(lengths),
+            _ => None,
+        };
+
+        // A string set only survives while neither side saw a string outside of one,
+        // and the union of their values stays small enough. Other types are unaffected.
+        self.type_mask |= other.type_mask;
+        if self.type_mask.contains(TypeMask::STRING_SET) {
+            match (&mut self.string_values, other.string_values) {
+                (Some(self_values), Some(other_values)) => self_values.extend(other_values),
+                (None, other_values) => self.string_values = other_values,
+                (Some(_), None) => {}
+            }
+
+            let small = matches!(
+                &self.string_values,
+                Some(values) if values.len() <= config.max_string_set_values
+            );
+            if self.type_mask.contains(TypeMask::STRING) || !small {
+                self.type_mask &= !TypeMask::STRING_SET;
+                self.type_mask |= TypeMask::STRING;
+                self.string_values = None;
             }
-        } else {
-            self.type_mask |= other.type_mask;
         }
 
         // Special case for arrays
@@ -216,56 +973,157 @@ impl Schema {
             }
         }
 
-        if self.type_mask.contains(TypeMask::OBJECT) {
-            if let Some(self_props) = &self.object_properties {
-                if self_props.len() > config.max_object_keys {
-                    self.type_mask &= !TypeMask::OBJECT; // remove object
-                    self.type_mask |= TypeMask::LARGE_OBJ; // add large object
-                    self.object_properties = None; // remove properties
-                    return;
-                }
+        // Once either side is a large object, both are treated as maps
+        // and only the schema of their values is kept
+        if self.type_mask.contains(TypeMask::LARGE_OBJ) {
+            let mut other = Schema {
+                object_properties: other.object_properties,
+                map_values: other.map_values,
+                key_format: other.key_format,
+                ..Schema::new(other.type_mask)
+            };
+            other.convert_to_map(config);
+            self.convert_to_map(config);
+
+            match (&mut self.map_values, other.map_values) {
+                (Some(self_values), Some(other_values)) => self_values.merge(*other_values, config),
+                (None, Some(other_values)) => self.map_values = Some(other_values),
+                _ => {}
             }
+
+            self.key_format = match (self.key_format, other.key_format) {
+                (Some(self_format), Some(other_format)) => Some(self_format & other_format),
+                (self_format, other_format) => self_format.or(other_format),
+            };
+            return;
         }
 
         match (&mut self.object_properties, other.object_properties) {
             (Some(self_props), Some(other_props)) => {
-                // we have to handle from both sides to account for absent keys in either
+                // we have to handle from both sides to account for absent keys in either.
+                // self's keys keep their position and new keys from other are appended,
+                // so properties stay in first-seen order.
 
-                let mut leftover_self_props = std::mem::take(self_props);
+                let self_len = self_props.len();
+                let mut matched = vec![false; self_len];
 
-                // let mut new_props = HashMap::new();
                 for (key, mut other_prop) in other_props {
-                    match leftover_self_props.remove(&key) {
-                        Some(mut self_prop) => {
+                    match self_props.get_full_mut(&key) {
+                        Some((index, _, self_prop)) => {
                             self_prop.merge(other_prop, config);
-                            self_props.insert(key, self_prop);
+                            matched[index] = true;
                         }
                         None => {
                             other_prop.type_mask |= TypeMask::ABSENT;
+                            other_prop.add_unseen(self_objects);
                             self_props.insert(key, other_prop);
                         }
                     }
                 }
 
-                for (key, mut self_prop) in leftover_self_props {
+                for (self_prop, _) in self_props
+                    .values_mut()
+                    .zip(matched)
+                    .filter(|(_, matched)| !matched)
+                {
                     self_prop.type_mask |= TypeMask::ABSENT;
-                    self_props.insert(key, self_prop);
+                    self_prop.add_unseen(other_objects);
                 }
             }
             (None, Some(mut other_props)) => {
-                for other_prop in other_props.values_mut() {
-                    other_prop.type_mask |= TypeMask::ABSENT;
+                if self_has_objects {
+                    for other_prop in other_props.values_mut() {
+                        other_prop.type_mask |= TypeMask::ABSENT;
+                        other_prop.add_unseen(self_objects);
+                    }
                 }
                 self.object_properties = Some(other_props);
             }
-            (Some(self_props), None) => {
+            (Some(self_props), None) if other_has_objects => {
                 let mut self_props = std::mem::take(self_props);
                 for self_prop in self_props.values_mut() {
                     self_prop.type_mask |= TypeMask::ABSENT;
+                    self_prop.add_unseen(other_objects);
                 }
                 self.object_properties = Some(self_props);
             }
             _ => {}
         }
+
+        if let Some(self_props) = &self.object_properties {
+            if self_props.len() > config.max_object_keys {
+                self.convert_to_map(config);
+            }
+        }
+    }
+
+    /// Whether an object's keys all share a data-like shape (such as UUIDs or numeric IDs)
+    /// and its values all share the same type, suggesting it is a map rather than a record.
+    fn looks_like_map(&self) -> bool {
+        let Some(properties) = &self.object_properties else {
+            return false;
+        };
+        let Some((_, first)) = properties.first() else {
+            return false;
+        };
+
+        let key_format = properties
+            .keys()
+            .fold(StringFormat::DATA_KEYS, |format, key| {
+                format & StringFormat::detect(key)
+            });
+        if key_format.is_empty() {
+            return false;
+        }
+
+        properties.values().all(|value| {
+            value.type_mask == first.type_mask
+                && match (&value.object_properties, &first.object_properties) {
+                    (Some(value_props), Some(first_props)) => {
+                        value_props.is_empty() && first_props.is_empty()
+                            || value_props.keys().any(|key| first_props.contains_key(key))
+                    }
+                    _ => true,
+                }
+        })
     }
-}
\ No newline at end of file
+
+    /// Turn an object into a map ("large object"), merging the schemas of all its
+    /// properties into `map_values` and recording the shape of its keys.
+    /// Does nothing to schemas that are not objects.
+    fn convert_to_map(&mut self, config: &Config) {
+        if !self
+            .type_mask
+            .intersects(TypeMask::OBJECT | TypeMask::LARGE_OBJ)
+        {
+            return;
+        }
+
+        self.type_mask &= !TypeMask::OBJECT; // remove object
+        self.type_mask |= TypeMask::LARGE_OBJ; // add large object
+        self.variants = None; // maps are not unions
+
+        let Some(properties) = self.object_properties.take() else {
+            return;
+        };
+
+        for (key, mut value) in properties {
+            // every value of a map is present, so absence no longer applies
+            value.type_mask &= !TypeMask::ABSENT;
+            if let Some(counts) = &mut value.counts {
+                counts.seen = counts.present;
+            }
+
+            let key_format = StringFormat::detect(&key);
+            self.key_format = Some(match self.key_format {
+                Some(format) => format & key_format,
+                None => key_format,
+            });
+
+            match &mut self.map_values {
+                Some(values) => values.merge(value, config),
+                None => self.map_values = Some(Box::new(value)),
+            }
+        }
+    }
+}
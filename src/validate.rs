@@ -0,0 +1,646 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+use simd_json::{prelude::Writable, BorrowedValue, StaticNode};
+
+use crate::format::StringFormat;
+use crate::process::Location;
+use crate::report::escape;
+
+/// A part of a record that does not match its schema.
+#[derive(Clone, Debug)]
+pub struct Violation {
+    /// Where in the record, as a JSON pointer. Array items and additional properties
+    /// are addressed with a `*` segment, as in the `--report` file.
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Assertions and applicators of Draft 2020-12 (and Draft-07) that are not checked.
+const UNSUPPORTED_KEYWORDS: [&str; 14] = [
+    "multipleOf",
+    "uniqueItems",
+    "contains",
+    "minContains",
+    "maxContains",
+    "patternProperties",
+    "dependentRequired",
+    "dependentSchemas",
+    "dependencies",
+    "if",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+    "$dynamicRef",
+    "$recursiveRef",
+];
+
+/// Checks values against a Draft 2020-12 JSON Schema.
+///
+/// Supports every keyword this tool emits, plus the other common assertions
+/// (`allOf`, `not`, `exclusiveMinimum`, `minLength`, ...). `format` is treated as an
+/// annotation, as the draft specifies by default, and `pattern` is only checked for
+/// the expressions this tool emits for map keys. Schemas with other assertions are
+/// rejected up front, so that no record passes a check that was never made.
+pub struct Validator {
+    root: Value,
+}
+
+impl Validator {
+    pub fn new(root: Value) -> Result<Self> {
+        if !root.is_object() && !root.is_boolean() {
+            bail!("A schema must be an object or a boolean");
+        }
+        let validator = Validator { root };
+
+        let mut unsupported = BTreeSet::new();
+        validator.inspect(&validator.root, "", &mut unsupported, &mut BTreeSet::new())?;
+        if !unsupported.is_empty() {
+            let unsupported: Vec<String> = unsupported.into_iter().collect();
+            bail!(
+                "The schema uses keywords that cannot be checked: {}",
+                unsupported.join(", ")
+            );
+        }
+
+        Ok(validator)
+    }
+
+    /// Walk `schema`, at `pointer`, and every subschema it uses, collecting the keywords
+    /// that are not checked. Fails on a `$ref` that cannot be resolved, or one that leads
+    /// back to the same schema without descending into the value, as validating with it
+    /// would never end.
+    fn inspect<'a>(
+        &'a self,
+        schema: &'a Value,
+        pointer: &str,
+        unsupported: &mut BTreeSet<String>,
+        references: &mut BTreeSet<&'a str>,
+    ) -> Result<()> {
+        let Value::Object(object) = schema else {
+            return Ok(());
+        };
+        self.follow(schema, &mut Vec::new())?;
+
+        let location = if pointer.is_empty() {
+            "(root)"
+        } else {
+            pointer
+        };
+        let keywords = UNSUPPORTED_KEYWORDS
+            .into_iter()
+            .filter(|keyword| object.contains_key(*keyword))
+            .map(str::to_string);
+        let pattern = object
+            .get("pattern")
+            .and_then(Value::as_str)
+            .filter(|pattern| StringFormat::from_pattern(pattern).is_none())
+            .map(|pattern| format!("pattern {pattern}"));
+        let tuple = matches!(object.get("items"), Some(Value::Array(_)))
+            .then(|| "items as an array".to_string());
+        for keyword in keywords.chain(pattern).chain(tuple) {
+            unsupported.insert(format!("{keyword} at {location}"));
+        }
+
+        if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+            let Some(target) = self.resolve(reference) else {
+                bail!("Cannot resolve $ref {reference} at {location}");
+            };
+            if references.insert(reference) {
+                let target_pointer = reference.trim_start_matches('#');
+                self.inspect(target, target_pointer, unsupported, references)?;
+            }
+        }
+
+        for keyword in ["properties", "$defs", "definitions"] {
+            let subschemas = object.get(keyword).and_then(Value::as_object);
+            for (key, subschema) in subschemas.into_iter().flatten() {
+                let child = format!("{}/{}/{}", pointer, keyword, escape(key));
+                self.inspect(subschema, &child, unsupported, references)?;
+            }
+        }
+        for keyword in ["allOf", "anyOf", "oneOf", "prefixItems"] {
+            let subschemas = object.get(keyword).and_then(Value::as_array);
+            for (index, subschema) in subschemas.into_iter().flatten().enumerate() {
+                let child = format!("{}/{}/{}", pointer, keyword, index);
+                self.inspect(subschema, &child, unsupported, references)?;
+            }
+        }
+        for keyword in ["items", "additionalProperties", "propertyNames", "not"] {
+            if let Some(subschema) = object.get(keyword) {
+                let child = format!("{}/{}", pointer, keyword);
+                self.inspect(subschema, &child, unsupported, references)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follow the subschemas applied to the same value as `schema`, failing if
+    /// one of them is already on `stack`.
+    fn follow<'a>(&'a self, schema: &'a Value, stack: &mut Vec<&'a Value>) -> Result<()> {
+        let Value::Object(object) = schema else {
+            return Ok(());
+        };
+        stack.push(schema);
+
+        if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+            if let Some(target) = self.resolve(reference) {
+                if stack.iter().any(|seen| std::ptr::eq(*seen, target)) {
+                    bail!(
+                        "$ref {reference} refers back to itself without descending into the value"
+                    );
+                }
+                self.follow(target, stack)?;
+            }
+        }
+
+        let applicators = ["allOf", "anyOf", "oneOf"]
+            .into_iter()
+            .filter_map(|keyword| object.get(keyword).and_then(Value::as_array))
+            .flatten()
+            .chain(object.get("not"));
+        for subschema in applicators {
+            self.follow(subschema, stack)?;
+        }
+
+        stack.pop();
+        Ok(())
+    }
+
+    /// Every violation in `value`, in document order.
+    pub fn validate(&self, value: &BorrowedValue) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.check(&self.root, value, &mut String::new(), &mut violations);
+        violations
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        value: &BorrowedValue,
+        pointer: &mut String,
+        violations: &mut Vec<Violation>,
+    ) {
+        let schema = match schema {
+            Value::Bool(false) => return report(violations, pointer, "no value is allowed here"),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(target, value, pointer, violations),
+                None => report(
+                    violations,
+                    pointer,
+                    format!("cannot resolve $ref {reference}"),
+                ),
+            }
+        }
+
+        if let Some(types) = schema.get("type") {
+            let matches = match types {
+                Value::String(name) => has_type(value, name),
+                Value::Array(names) => names
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|name| has_type(value, name)),
+                _ => true,
+            };
+            if !matches {
+                let expected = match types {
+                    Value::String(name) => name.clone(),
+                    _ => types.to_string(),
+                };
+                let message = format!("expected {}, found {}", expected, type_name(value));
+                report(violations, pointer, message);
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if !equals(value, expected) {
+                report(
+                    violations,
+                    pointer,
+                    format!("expected {}, found {}", expected, value.encode()),
+                );
+            }
+        }
+
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            if !allowed.iter().any(|expected| equals(value, expected)) {
+                let message = format!(
+                    "{} is not one of {} allowed values",
+                    value.encode(),
+                    allowed.len()
+                );
+                report(violations, pointer, message);
+            }
+        }
+
+        match value {
+            BorrowedValue::String(string) => self.check_string(schema, string, pointer, violations),
+            BorrowedValue::Array(items) => self.check_array(schema, items, pointer, violations),
+            BorrowedValue::Object(object) => self.check_object(schema, object, pointer, violations),
+            BorrowedValue::Static(_) => self.check_number(schema, value, pointer, violations),
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for schema in schemas {
+                self.check(schema, value, pointer, violations);
+            }
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            if !schemas.iter().any(|schema| self.is_valid(schema, value)) {
+                report(violations, pointer, "matches none of the anyOf schemas");
+            }
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            match schemas
+                .iter()
+                .filter(|schema| self.is_valid(schema, value))
+                .count()
+            {
+                0 => report(violations, pointer, "matches none of the oneOf schemas"),
+                1 => {}
+                matches => {
+                    let message = format!("matches {matches} of the oneOf schemas instead of one");
+                    report(violations, pointer, message);
+                }
+            }
+        }
+
+        if let Some(schema) = schema.get("not") {
+            if self.is_valid(schema, value) {
+                report(violations, pointer, "matches the schema under not");
+            }
+        }
+    }
+
+    fn check_number(
+        &self,
+        schema: &Map<String, Value>,
+        value: &BorrowedValue,
+        pointer: &str,
+        violations: &mut Vec<Violation>,
+    ) {
+        use Ordering::{Equal, Greater, Less};
+        let bounds: [(&str, &[Ordering]); 4] = [
+            ("minimum", &[Greater, Equal]),
+            ("maximum", &[Less, Equal]),
+            ("exclusiveMinimum", &[Greater]),
+            ("exclusiveMaximum", &[Less]),
+        ];
+        for (keyword, allowed) in bounds {
+            let Some(bound) = schema.get(keyword) else {
+                continue;
+            };
+            if let Some(ordering) = compare(value, bound) {
+                if !allowed.contains(&ordering) {
+                    let message = format!("{} violates {} {}", value.encode(), keyword, bound);
+                    report(violations, pointer, message);
+                }
+            }
+        }
+    }
+
+    fn check_string(
+        &self,
+        schema: &Map<String, Value>,
+        string: &str,
+        pointer: &str,
+        violations: &mut Vec<Violation>,
+    ) {
+        let length = string.chars().count() as u64;
+        if let Some(min_length) = schema.get("minLength").and_then(Value::as_u64) {
+            if length < min_length {
+                report(
+                    violations,
+                    pointer,
+                    format!("shorter than {min_length} characters"),
+                );
+            }
+        }
+        if let Some(max_length) = schema.get("maxLength").and_then(Value::as_u64) {
+            if length > max_length {
+                report(
+                    violations,
+                    pointer,
+                    format!("longer than {max_length} characters"),
+                );
+            }
+        }
+
+        // Only the expressions this tool emits can be checked, without a regex engine
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            if let Some(format) = StringFormat::from_pattern(pattern) {
+                if !StringFormat::detect(string).contains(format) {
+                    report(violations, pointer, format!("does not match {pattern}"));
+                }
+            }
+        }
+    }
+
+    fn check_array(
+        &self,
+        schema: &Map<String, Value>,
+        items: &[BorrowedValue],
+        pointer: &mut String,
+        violations: &mut Vec<Violation>,
+    ) {
+        let length = items.len() as u64;
+        if let Some(min_items) = schema.get("minItems").and_then(Value::as_u64) {
+            if length < min_items {
+                let message = format!("has {length} items, fewer than {min_items}");
+                report(violations, pointer, message);
+            }
+        }
+        if let Some(max_items) = schema.get("maxItems").and_then(Value::as_u64) {
+            if length > max_items {
+                let message = format!("has {length} items, more than {max_items}");
+                report(violations, pointer, message);
+            }
+        }
+
+        let prefix_items = match schema.get("prefixItems") {
+            Some(Value::Array(prefix_items)) => prefix_items.as_slice(),
+            _ => &[],
+        };
+        for (index, (item, item_schema)) in items.iter().zip(prefix_items).enumerate() {
+            self.check_child(item_schema, item, &index.to_string(), pointer, violations);
+        }
+
+        if let Some(item_schema) = schema.get("items") {
+            for item in items.iter().skip(prefix_items.len()) {
+                self.check_child(item_schema, item, "*", pointer, violations);
+            }
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &simd_json::borrowed::Object,
+        pointer: &mut String,
+        violations: &mut Vec<Violation>,
+    ) {
+        let length = object.len() as u64;
+        if let Some(min_properties) = schema.get("minProperties").and_then(Value::as_u64) {
+            if length < min_properties {
+                let message = format!("has {length} properties, fewer than {min_properties}");
+                report(violations, pointer, message);
+            }
+        }
+        if let Some(max_properties) = schema.get("maxProperties").and_then(Value::as_u64) {
+            if length > max_properties {
+                let message = format!("has {length} properties, more than {max_properties}");
+                report(violations, pointer, message);
+            }
+        }
+
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    let child = format!("{}/{}", pointer, escape(key));
+                    report(violations, &child, "required property is missing");
+                }
+            }
+        }
+
+        let additional_properties = schema.get("additionalProperties");
+        let property_names = schema.get("propertyNames");
+        for (key, value) in object.iter() {
+            match properties.and_then(|properties| properties.get(key.as_ref())) {
+                Some(property_schema) => {
+                    self.check_child(property_schema, value, &escape(key), pointer, violations)
+                }
+                // Unexpected keys are worth naming, while map values are all alike
+                None => match additional_properties {
+                    Some(Value::Bool(false)) => {
+                        let child = format!("{}/{}", pointer, escape(key));
+                        report(violations, &child, "property is not allowed");
+                    }
+                    Some(values_schema) => {
+                        self.check_child(values_schema, value, "*", pointer, violations)
+                    }
+                    None => {}
+                },
+            }
+
+            if let Some(names_schema) = property_names {
+                let name = BorrowedValue::String(key.clone());
+                if !self.is_valid(names_schema, &name) {
+                    report(
+                        violations,
+                        pointer,
+                        format!("invalid property name {}", name.encode()),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Check `value` as the child of the value at `pointer`, at `segment`.
+    fn check_child(
+        &self,
+        schema: &Value,
+        value: &BorrowedValue,
+        segment: &str,
+        pointer: &mut String,
+        violations: &mut Vec<Violation>,
+    ) {
+        let length = pointer.len();
+        pointer.push('/');
+        pointer.push_str(segment);
+        self.check(schema, value, pointer, violations);
+        pointer.truncate(length);
+    }
+
+    fn is_valid(&self, schema: &Value, value: &BorrowedValue) -> bool {
+        let mut violations = Vec::new();
+        self.check(schema, value, &mut String::new(), &mut violations);
+        violations.is_empty()
+    }
+
+    /// Resolve a reference within the schema itself, such as `#` or `#/$defs/node`.
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        self.root.pointer(reference.strip_prefix('#')?)
+    }
+}
+
+fn report(violations: &mut Vec<Violation>, pointer: &str, message: impl Into<String>) {
+    violations.push(Violation {
+        pointer: pointer.to_string(),
+        message: message.into(),
+    });
+}
+
+fn type_name(value: &BorrowedValue) -> &'static str {
+    match value {
+        BorrowedValue::Static(StaticNode::Null) => "null",
+        BorrowedValue::Static(StaticNode::Bool(_)) => "boolean",
+        BorrowedValue::Static(StaticNode::I64(_) | StaticNode::U64(_)) => "integer",
+        BorrowedValue::Static(StaticNode::F64(_)) => "number",
+        BorrowedValue::String(_) => "string",
+        BorrowedValue::Array(_) => "array",
+        BorrowedValue::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &BorrowedValue, name: &str) -> bool {
+    match (name, value) {
+        // Integers are numbers, and floats without a fractional part are integers
+        ("integer", BorrowedValue::Static(StaticNode::F64(float))) => float.fract() == 0.0,
+        ("number", _) => matches!(type_name(value), "integer" | "number"),
+        _ => type_name(value) == name,
+    }
+}
+
+/// A number as either an exact integer or a float, so that large integers compare exactly.
+#[derive(Clone, Copy)]
+enum Number {
+    Integer(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn from_value(value: &BorrowedValue) -> Option<Number> {
+        match value {
+            BorrowedValue::Static(StaticNode::I64(value)) => Some(Number::Integer(*value as i128)),
+            BorrowedValue::Static(StaticNode::U64(value)) => Some(Number::Integer(*value as i128)),
+            BorrowedValue::Static(StaticNode::F64(value)) => Some(Number::Float(*value)),
+            _ => None,
+        }
+    }
+
+    fn from_json(value: &Value) -> Option<Number> {
+        let number = value.as_number()?;
+        match (number.as_i64(), number.as_u64()) {
+            (Some(value), _) => Some(Number::Integer(value as i128)),
+            (_, Some(value)) => Some(Number::Integer(value as i128)),
+            _ => number.as_f64().map(Number::Float),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Integer(value) => value as f64,
+            Number::Float(value) => value,
+        }
+    }
+}
+
+/// How a numeric `value` compares to a numeric `bound`, if both are numbers.
+fn compare(value: &BorrowedValue, bound: &Value) -> Option<Ordering> {
+    match (Number::from_value(value)?, Number::from_json(bound)?) {
+        (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(&b)),
+        (a, b) => a.as_f64().partial_cmp(&b.as_f64()),
+    }
+}
+
+/// JSON equality, under which `1` and `1.0` are the same number.
+fn equals(value: &BorrowedValue, expected: &Value) -> bool {
+    match (value, expected) {
+        (BorrowedValue::Static(StaticNode::Null), Value::Null) => true,
+        (BorrowedValue::Static(StaticNode::Bool(a)), Value::Bool(b)) => a == b,
+        (BorrowedValue::String(a), Value::String(b)) => a == b,
+        (BorrowedValue::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equals(a, b))
+        }
+        (BorrowedValue::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && b.iter().all(|(key, b)| {
+                    a.get(key.as_str())
+                        .is_some_and(|a: &BorrowedValue| equals(a, b))
+                })
+        }
+        (value, Value::Number(_)) => compare(value, expected) == Some(Ordering::Equal),
+        _ => false,
+    }
+}
+
+/// An offending line, as shown in the summary.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub location: Location,
+    pub message: String,
+}
+
+/// The violations at one JSON pointer.
+#[derive(Clone, Debug, Default)]
+pub struct PointerReport {
+    /// The number of violations.
+    pub count: u64,
+    /// The first few, in input order.
+    pub samples: Vec<Sample>,
+}
+
+impl PointerReport {
+    /// Keep `sample` if it is among the first `max_samples` seen in the input.
+    fn add_sample(&mut self, sample: Sample, max_samples: usize) {
+        let index = self
+            .samples
+            .partition_point(|kept| kept.location < sample.location);
+        if index < max_samples {
+            self.samples.insert(index, sample);
+            self.samples.truncate(max_samples);
+        }
+    }
+}
+
+/// The outcome of validating a file, aggregated by JSON pointer.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    /// The number of records checked.
+    pub records: u64,
+    /// The number of records with at least one violation.
+    pub invalid: u64,
+    pub pointers: BTreeMap<String, PointerReport>,
+}
+
+impl ValidationReport {
+    pub fn add(&mut self, violations: Vec<Violation>, location: Location, max_samples: usize) {
+        self.records += 1;
+        if violations.is_empty() {
+            return;
+        }
+
+        self.invalid += 1;
+        for violation in violations {
+            let pointer = self.pointers.entry(violation.pointer).or_default();
+            pointer.count += 1;
+            let sample = Sample {
+                location,
+                message: violation.message,
+            };
+            pointer.add_sample(sample, max_samples);
+        }
+    }
+
+    /// The location of every sample kept.
+    pub fn locations_mut(&mut self) -> impl Iterator<Item = &mut Location> {
+        self.pointers
+            .values_mut()
+            .flat_map(|pointer| pointer.samples.iter_mut())
+            .map(|sample| &mut sample.location)
+    }
+
+    pub fn merge(&mut self, other: ValidationReport, max_samples: usize) {
+        self.records += other.records;
+        self.invalid += other.invalid;
+        for (pointer, other_report) in other.pointers {
+            let report = self.pointers.entry(pointer).or_default();
+            report.count += other_report.count;
+            for sample in other_report.samples {
+                report.add_sample(sample, max_samples);
+            }
+        }
+    }
+}